@@ -1,4 +1,25 @@
 CREATE EXTENSION IF NOT EXISTS pgcrypto; -- For gen_random_uuid()
+CREATE EXTENSION IF NOT EXISTS pg_trgm; -- For fuzzy username search
+
+-- Sets `updated_at` whenever a row is modified (unless the update sets it itself)
+CREATE OR REPLACE FUNCTION diesel_manage_updated_at(_tbl regclass) RETURNS VOID AS $$
+BEGIN
+    EXECUTE format('CREATE TRIGGER set_updated_at BEFORE UPDATE ON %s
+                    FOR EACH ROW EXECUTE PROCEDURE diesel_set_updated_at()', _tbl);
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION diesel_set_updated_at() RETURNS trigger AS $$
+BEGIN
+    IF (
+        NEW IS DISTINCT FROM OLD AND
+        NEW.updated_at IS NOT DISTINCT FROM OLD.updated_at
+    ) THEN
+        NEW.updated_at := current_timestamp;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
 
 CREATE TABLE users (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
@@ -7,11 +28,66 @@ CREATE TABLE users (
     phone_number TEXT NOT NULL UNIQUE CHECK (phone_number ~ '^\+?[0-9]{7,15}$'),
     two_factor_auth BOOLEAN NOT NULL DEFAULT false,
     password_hash TEXT NOT NULL,
-    profile_pic TEXT, -- Link to pfp img
+    legacy_profile_pic TEXT, -- Base64 data URL from before media storage, emptied on startup once moved
     bio TEXT, -- Short text about the user
-    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    username_normalised TEXT NOT NULL, -- Case-folded NFKC form of the username
+    username_skeleton TEXT, -- Unicode confusable skeleton of the username
+    is_admin BOOLEAN NOT NULL DEFAULT false,
+    profile_pic_id UUID, -- Media object of the profile picture
+    email_verified BOOLEAN NOT NULL DEFAULT false,
+    phone_number_verified BOOLEAN NOT NULL DEFAULT false,
+    phone_number_invalid BOOLEAN NOT NULL DEFAULT false, -- Stored number could not be normalised to a mobile in E.164 form
+    deleted_at TIMESTAMPTZ, -- Set when the account is deleted, hides it from other users
+    display_name TEXT,
+    pronouns TEXT,
+    status_text TEXT,
+    status_emoji TEXT,
+    status_expires_at TIMESTAMPTZ, -- NULL keeps the status until it is cleared
+    timezone TEXT, -- IANA time zone name, e.g. Europe/London
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(), -- Bumped by a trigger on every change, profile ETags are derived from it
+    legacy_profile_pic_invalid BOOLEAN NOT NULL DEFAULT false -- Legacy profile picture is not a usable image and was left in place
+);
+
+SELECT diesel_manage_updated_at('users');
+
+CREATE UNIQUE INDEX idx_users_username_normalised ON users (username_normalised);
+CREATE INDEX idx_users_username_skeleton ON users (username_skeleton);
+CREATE INDEX idx_users_username_normalised_prefix ON users (username_normalised text_pattern_ops);
+CREATE INDEX idx_users_username_normalised_trgm ON users USING GIN (username_normalised gin_trgm_ops);
+CREATE INDEX idx_users_email_lower ON users (lower(email));
+CREATE INDEX idx_users_display_name_trgm ON users USING GIN (lower(display_name) gin_trgm_ops);
+
+CREATE TABLE media (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    owner_id UUID NOT NULL,
+    content_hash TEXT NOT NULL, -- SHA-256 of the stored bytes, also the storage key
+    content_type TEXT NOT NULL,
+    byte_size BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    kind TEXT NOT NULL DEFAULT 'upload', -- 'upload' / 'avatar'
+    width INTEGER,
+    height INTEGER,
+    variant_of UUID, -- Original this is a resized variant of
+    CONSTRAINT fk_media_owner FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT fk_media_variant_of FOREIGN KEY (variant_of) REFERENCES media(id) ON DELETE CASCADE,
+    CONSTRAINT uq_media_owner_content UNIQUE (owner_id, content_hash)
+);
+
+CREATE INDEX idx_media_content_hash ON media (content_hash);
+CREATE INDEX idx_media_variant_of ON media (variant_of);
+
+CREATE TABLE user_settings (
+    user_id UUID PRIMARY KEY,
+    version INTEGER NOT NULL, -- Schema version of the settings document
+    settings JSONB NOT NULL DEFAULT '{}', -- Missing keys take their default value
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_user_settings_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
 );
 
+ALTER TABLE users ADD CONSTRAINT fk_users_profile_pic
+    FOREIGN KEY (profile_pic_id) REFERENCES media(id) ON DELETE SET NULL;
+
 CREATE TABLE groups (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
     name TEXT NOT NULL,
@@ -40,19 +116,105 @@ CREATE TABLE friend_request (
     requester UUID NOT NULL,
     receiver UUID NOT NULL,
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'accepted', 'declined')),
+    responded_at TIMESTAMPTZ,
     PRIMARY KEY (requester, receiver),
     CONSTRAINT fk_friend_request_requester FOREIGN KEY (requester) REFERENCES users(id),
     CONSTRAINT fk_friend_request_receiver FOREIGN KEY (receiver) REFERENCES users(id)
 );
 
+CREATE INDEX idx_friend_request_receiver_pending ON friend_request (receiver) WHERE status = 'pending';
+
+CREATE TABLE friend_request_send (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    requester UUID NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_friend_request_send_requester FOREIGN KEY (requester) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_friend_request_send_requester ON friend_request_send (requester, sent_at);
+
 CREATE TABLE friend (
     user1 UUID NOT NULL,
     user2 UUID NOT NULL,
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
     PRIMARY KEY (user1, user2),
+    CONSTRAINT friend_canonical_order CHECK (user1 < user2),
     CONSTRAINT fk_friend_user1 FOREIGN KEY (user1) REFERENCES users(id),
     CONSTRAINT fk_friend_user2 FOREIGN KEY (user2) REFERENCES users(id)
 );
 
 CREATE INDEX idx_friend_user1 ON friend (user1);
-CREATE INDEX idx_friend_user2 ON friend (user2);
\ No newline at end of file
+CREATE INDEX idx_friend_user2 ON friend (user2);
+
+CREATE TABLE friend_meta (
+    user_id UUID NOT NULL,
+    friend_id UUID NOT NULL,
+    favourite BOOLEAN NOT NULL DEFAULT FALSE,
+    nickname TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, friend_id),
+    CONSTRAINT fk_friend_meta_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT fk_friend_meta_friend FOREIGN KEY (friend_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE TABLE friend_list (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    owner_id UUID NOT NULL,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_friend_list_owner FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX idx_friend_list_owner_name ON friend_list (owner_id, lower(name));
+
+CREATE TABLE friend_list_member (
+    list_id UUID NOT NULL,
+    friend_id UUID NOT NULL,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (list_id, friend_id),
+    CONSTRAINT fk_friend_list_member_list FOREIGN KEY (list_id) REFERENCES friend_list(id) ON DELETE CASCADE,
+    CONSTRAINT fk_friend_list_member_friend FOREIGN KEY (friend_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_friend_list_member_friend ON friend_list_member (friend_id);
+
+CREATE TABLE user_block (
+    blocker UUID NOT NULL,
+    blocked UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (blocker, blocked),
+    CONSTRAINT fk_user_block_blocker FOREIGN KEY (blocker) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT fk_user_block_blocked FOREIGN KEY (blocked) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_user_block_blocked ON user_block (blocked);
+
+CREATE TABLE dismissed_suggestion (
+    user_id UUID NOT NULL,
+    dismissed UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, dismissed),
+    CONSTRAINT fk_dismissed_suggestion_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT fk_dismissed_suggestion_dismissed FOREIGN KEY (dismissed) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE TABLE reserved_username_grant (
+    username_normalised TEXT PRIMARY KEY, -- Case-folded NFKC form of the reserved name
+    user_id UUID NOT NULL,
+    granted_by UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_reserved_username_grant_user FOREIGN KEY (user_id) REFERENCES users(id),
+    CONSTRAINT fk_reserved_username_grant_granted_by FOREIGN KEY (granted_by) REFERENCES users(id)
+);
+CREATE TABLE username_history (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    username TEXT NOT NULL,
+    username_normalised TEXT NOT NULL, -- Case-folded NFKC form of the former username
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_username_history_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_username_history_username_normalised ON username_history (username_normalised, changed_at);
+CREATE INDEX idx_username_history_user_id ON username_history (user_id, changed_at);
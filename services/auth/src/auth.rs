@@ -1,42 +1,44 @@
 use actix_web::web;
-use bcrypt;
 use chrono::Utc;
 use diesel::dsl::insert_into;
 use diesel::prelude::*;
-use diesel::result::{DatabaseErrorKind as DieselDbError, Error as DieselError};
 use diesel_async::RunQueryDsl;
 
 use shared::database::PGPool;
+use shared::error::AppError;
 use shared::models::User;
+use shared::username::{fold_username, username_skeleton};
+use shared::validate::unique_violation_to_validation;
 
 pub async fn authenticate_user(
     pool: web::Data<PGPool>,
     uname: &str,
     pass: &str,
-) -> Result<User, DieselError> {
+) -> Result<User, AppError> {
     use shared::models::User;
     use shared::schema::users::dsl::*;
 
-    let mut conn = pool.get().await.map_err(|e| {
-        eprintln!(
-            "{:?}: Failed to acquire DB connection: {:?}",
-            Utc::now().timestamp() as usize,
-            e
-        );
-        DieselError::DatabaseError(DieselDbError::UnableToSendCommand, Box::new(e.to_string()))
-    })?;
+    let mut conn = pool.get().await?;
 
-    let user_result = users
-        .filter(username.eq(uname))
-        .first::<User>(&mut conn)
-        .await;
+    let user = users
+        .filter(username_normalised.eq(fold_username(uname)))
+        .select(User::as_select())
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(incorrect_login)?;
 
-    match user_result {
-        Ok(user) => match bcrypt::verify(pass, &user.password_hash) {
-            Ok(true) => Ok(user),
-            Ok(false) | Err(_) => Err(DieselError::NotFound),
-        },
-        Err(_) => Err(DieselError::NotFound),
+    match bcrypt::verify(pass, &user.password_hash) {
+        Ok(true) => Ok(user),
+        Ok(false) => Err(incorrect_login()),
+        Err(e) => {
+            eprintln!(
+                "{:?}: Password verification failed: {:?}",
+                Utc::now().timestamp() as usize,
+                e
+            );
+            Err(incorrect_login())
+        }
     }
 }
 
@@ -46,21 +48,16 @@ pub async fn add_user_to_db(
     email: &str,
     phone_number: &str,
     password_hash: &str,
-) -> Result<usize, DieselError> {
+) -> Result<usize, AppError> {
     use shared::models::RegisterUser;
     use shared::schema::users;
 
-    let mut conn = pool.get().await.map_err(|e| {
-        eprintln!(
-            "{:?}: Failed to acquire DB connection: {:?}",
-            Utc::now().timestamp() as usize,
-            e
-        );
-        DieselError::DatabaseError(DieselDbError::UnableToSendCommand, Box::new(e.to_string()))
-    })?;
+    let mut conn = pool.get().await?;
 
     let new_user = RegisterUser {
         username: username.to_string(),
+        username_normalised: fold_username(username),
+        username_skeleton: username_skeleton(username),
         email: email.to_string(),
         phone_number: phone_number.to_string(),
         password_hash: password_hash.to_string(),
@@ -70,4 +67,9 @@ pub async fn add_user_to_db(
         .values(&new_user)
         .execute(&mut conn)
         .await
+        .map_err(unique_violation_to_validation)
+}
+
+pub fn incorrect_login() -> AppError {
+    AppError::unauthorized("incorrect_login", "incorrect login details")
 }
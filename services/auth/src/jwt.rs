@@ -1,7 +1,7 @@
 use actix_web::cookie::{Cookie, SameSite, time};
 use actix_web::http::header::ContentType;
 use actix_web::web;
-use actix_web::{HttpRequest, HttpResponse, Responder, post};
+use actix_web::{HttpRequest, HttpResponse, post};
 use chrono::Utc;
 use opentelemetry::{
     KeyValue, global,
@@ -9,11 +9,15 @@ use opentelemetry::{
 };
 
 use shared::database::PGPool;
+use shared::error::AppError;
 use shared::jwt::{JwtTokenKind, encode_jwt_token, extract_user_id};
 use shared::profile::get_user_by_id;
 
 #[post("/refresh")]
-pub async fn post_refresh(pool: web::Data<PGPool>, req: HttpRequest) -> impl Responder {
+pub async fn post_refresh(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
     let tracer = global::tracer("my_tracer");
 
     let mut span = tracer.start("post_refresh");
@@ -28,57 +32,43 @@ pub async fn post_refresh(pool: web::Data<PGPool>, req: HttpRequest) -> impl Res
     // extract user id from refresh token
     let user_id = match extract_user_id(&req, JwtTokenKind::REFRESH) {
         Ok(id) => id,
-        Err(resp) => {
+        Err(e) => {
             span.end();
-            return resp;
+            return Err(e);
         }
     };
 
-    match get_user_by_id(pool, &user_id).await {
-        Ok(_) => {
-            let new_access_token = match encode_jwt_token(user_id.to_string(), JwtTokenKind::ACCESS)
-            {
-                Ok(token) => token,
-                Err(e) => {
-                    eprintln!(
-                        "{:?}: Failed to encode access token: {:?}",
-                        Utc::now().timestamp() as usize,
-                        e
-                    );
-
-                    span.end();
-                    return HttpResponse::InternalServerError()
-                        .content_type(ContentType::json())
-                        .body(r#"{"detail":"internal server error"}"#);
-                }
-            };
+    if let Err(e) = get_user_by_id(pool, &user_id).await {
+        eprintln!(
+            "{:?}: User fetching failed: {:?}",
+            Utc::now().timestamp() as usize,
+            e
+        );
 
-            let access_cookie = Cookie::build("access_token", &new_access_token)
-                .secure(false) // for localhost, enable secure for HTTPS in prod
-                .http_only(true)
-                .max_age(time::Duration::minutes(15))
-                .same_site(SameSite::Lax)
-                .path("/")
-                .domain("127.0.0.1")
-                .finish();
+        span.end();
+        return Err(AppError::forbidden("user_not_found", "user not found"));
+    }
 
-            span.end();
-            HttpResponse::Ok()
-                .content_type(ContentType::json())
-                .cookie(access_cookie)
-                .body(r#"{"detail":"access token refreshed successfully"}"#)
-        }
+    let new_access_token = match encode_jwt_token(user_id.to_string(), JwtTokenKind::ACCESS) {
+        Ok(token) => token,
         Err(e) => {
-            eprintln!(
-                "{:?}: User fetching failed: {:?}",
-                Utc::now().timestamp() as usize,
-                e
-            );
-
             span.end();
-            HttpResponse::Forbidden()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"User not found"}"#)
+            return Err(e.into());
         }
-    }
+    };
+
+    let access_cookie = Cookie::build("access_token", &new_access_token)
+        .secure(false) // for localhost, enable secure for HTTPS in prod
+        .http_only(true)
+        .max_age(time::Duration::minutes(15))
+        .same_site(SameSite::Lax)
+        .path("/")
+        .domain("127.0.0.1")
+        .finish();
+
+    span.end();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .cookie(access_cookie)
+        .body(r#"{"detail":"access token refreshed successfully"}"#))
 }
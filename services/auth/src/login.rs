@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use actix_web::cookie::{Cookie, SameSite, time};
 use actix_web::http::header::ContentType;
-use actix_web::{HttpRequest, HttpResponse, Responder, post, web};
+use actix_web::{HttpRequest, HttpResponse, post, web};
 use chrono::Utc;
 use opentelemetry::trace::TraceContextExt;
 use opentelemetry::{
@@ -15,8 +15,11 @@ use serde_json::to_string;
 use crate::auth::authenticate_user;
 use shared::csrf::verify_csrf_token;
 use shared::database::PGPool;
+use shared::error::AppError;
 use shared::jwt::generate_jwt_tokens_for_user;
-use shared::validate::{validate_existing_username, validate_password};
+use shared::media::media_url;
+use shared::username::normalise_username;
+use shared::validate::{validate_existing_password, validate_existing_username};
 
 #[derive(Deserialize)]
 struct LoginForm {
@@ -29,7 +32,7 @@ pub async fn post_login(
     pool: web::Data<PGPool>,
     req_body: web::Json<LoginForm>,
     req: HttpRequest,
-) -> impl Responder {
+) -> Result<HttpResponse, AppError> {
     let tracer = global::tracer("my_tracer");
 
     let mut init_span = tracer.start("post_login");
@@ -50,13 +53,11 @@ pub async fn post_login(
 
     if !verify_csrf {
         csrf_span.end();
-        return HttpResponse::Unauthorized()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"csrf failed"}"#);
+        return Err(AppError::csrf_failed());
     }
     csrf_span.end();
 
-    let username = req_body.username.trim();
+    let username = &normalise_username(&req_body.username);
     let password = &req_body.password;
 
     let mut validate_username_span = tracer.start_with_context(
@@ -64,11 +65,9 @@ pub async fn post_login(
         &Context::current().with_span(csrf_span),
     );
     validate_username_span.set_attribute(KeyValue::new("rpc.method", "validate_existing_username"));
-    if !validate_existing_username(&username) {
+    if !validate_existing_username(username) {
         validate_username_span.end();
-        return HttpResponse::Unauthorized()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"invalid login"}"#);
+        return Err(invalid_login());
     }
     validate_username_span.end();
 
@@ -77,11 +76,9 @@ pub async fn post_login(
         &Context::current().with_span(validate_username_span),
     );
     validate_password_span.set_attribute(KeyValue::new("rpc.method", "validate_existing_password"));
-    if !validate_password(password.to_string()) {
+    if !validate_existing_password(password) {
         validate_password_span.end();
-        return HttpResponse::Unauthorized()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"invalid login"}"#);
+        return Err(invalid_login());
     }
     validate_password_span.end();
 
@@ -98,7 +95,10 @@ pub async fn post_login(
             map.insert("email", user.email);
             map.insert("phone_number", user.phone_number);
             map.insert("two_factor_auth", user.two_factor_auth.to_string());
-            map.insert("profile_pic", user.profile_pic.unwrap_or_default());
+            map.insert(
+                "profile_pic",
+                user.profile_pic_id.map(media_url).unwrap_or_default(),
+            );
             map.insert("bio", user.bio.unwrap_or_default());
             map.insert("created_at", user.created_at.to_string());
 
@@ -125,11 +125,11 @@ pub async fn post_login(
                 .finish();
 
             auth_span.end();
-            HttpResponse::Ok()
+            Ok(HttpResponse::Ok()
                 .content_type(ContentType::json())
                 .cookie(access_cookie)
                 .cookie(refresh_cookie)
-                .body(json_str)
+                .body(json_str))
         }
         Err(e) => {
             eprintln!(
@@ -139,9 +139,11 @@ pub async fn post_login(
             );
 
             auth_span.end();
-            HttpResponse::Unauthorized()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"incorrect login details"}"#)
+            Err(e)
         }
     }
 }
+
+fn invalid_login() -> AppError {
+    AppError::unauthorized("invalid_login", "invalid login")
+}
@@ -8,6 +8,9 @@ mod routes;
 
 use crate::routes::apply_routes;
 use shared::database::create_database_pool;
+use shared::error::json_error_handler;
+use shared::phone::backfill_phone_numbers;
+use shared::username::backfill_usernames;
 
 use actix_cors::Cors;
 use actix_web::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, HeaderName};
@@ -16,7 +19,7 @@ use chrono::Utc;
 use opentelemetry::global;
 use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
-use std::io::{Error, ErrorKind, Result};
+use std::io::{Error, Result};
 
 const SERVER_URL: &str = "0.0.0.0";
 const HTTP_SERVER_PORT: u16 = 8080;
@@ -48,7 +51,7 @@ async fn main() -> Result<()> {
     let pool = match result {
         Err(e) => {
             eprintln!("{}", e);
-            return Err(Error::new(ErrorKind::Other, e));
+            return Err(Error::other(e));
         }
         Ok(pool) => {
             println!(
@@ -59,6 +62,28 @@ async fn main() -> Result<()> {
         }
     };
 
+    // convert phone numbers stored in national format to E.164
+    match backfill_phone_numbers(&pool).await {
+        Ok(0) => {}
+        Ok(count) => println!(
+            "{:?}: Normalised {:?} phone numbers to E.164",
+            Utc::now().timestamp() as usize,
+            count
+        ),
+        Err(e) => eprintln!("{}", e),
+    }
+
+    // fold usernames created before they were normalised
+    match backfill_usernames(&pool).await {
+        Ok(0) => {}
+        Ok(count) => println!(
+            "{:?}: Normalised {:?} usernames",
+            Utc::now().timestamp() as usize,
+            count
+        ),
+        Err(e) => eprintln!("{}", e),
+    }
+
     // Run the HTTP server until application close
     println!(
         "{:?}: Starting Actix web server on {:?}:{:?}",
@@ -87,6 +112,7 @@ async fn main() -> Result<()> {
             )
             .configure(apply_routes)
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::JsonConfig::default().error_handler(json_error_handler))
     })
     .bind((SERVER_URL, HTTP_SERVER_PORT))?
     .run()
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use actix_web::cookie::{Cookie, SameSite, time};
 use actix_web::http::header::ContentType;
-use actix_web::{HttpRequest, HttpResponse, Responder, post, web};
+use actix_web::{HttpRequest, HttpResponse, post, web};
 use chrono::Utc;
 use opentelemetry::{
     KeyValue, global,
@@ -14,9 +14,13 @@ use serde_json::to_string;
 use crate::auth::{add_user_to_db, authenticate_user};
 use shared::csrf::verify_csrf_token;
 use shared::database::PGPool;
+use shared::error::AppError;
 use shared::jwt::generate_jwt_tokens_for_user;
+use shared::media::media_url;
+use shared::username::normalise_username;
 use shared::validate::{
-    validate_email, validate_new_username, validate_password, validate_phone_number,
+    ValidationErrors, validate_email_format, validate_password, validate_phone_number_format,
+    validate_unique_fields, validate_username_format,
 };
 
 #[derive(Deserialize)]
@@ -32,7 +36,7 @@ pub async fn post_register(
     pool: web::Data<PGPool>,
     req_body: web::Json<RegisterForm>,
     req: HttpRequest,
-) -> impl Responder {
+) -> Result<HttpResponse, AppError> {
     let tracer = global::tracer("my_tracer");
 
     let mut span = tracer.start("post_register");
@@ -44,76 +48,41 @@ pub async fn post_register(
         req.peer_addr()
     );
 
-    let verify_csrf = verify_csrf_token(&req);
-
-    if !verify_csrf {
+    if !verify_csrf_token(&req) {
         span.end();
-        return HttpResponse::Unauthorized()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"csrf failed"}"#);
+        return Err(AppError::csrf_failed());
     }
 
-    let username = req_body.username.trim();
+    let username = &normalise_username(&req_body.username);
     let email = &req_body.email;
-    let phone_number = &req_body.phone_number;
     let password = &req_body.password;
 
-    match validate_new_username(pool.clone(), &username).await {
-        Ok(valid) => {
-            if !valid {
-                span.end();
-                return HttpResponse::BadRequest()
-                    .content_type(ContentType::json())
-                    .body(r#"{"detail":"invalid username format"}"#);
-            }
-        }
-        Err(_) => {
-            span.end();
-            return HttpResponse::BadRequest()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"username taken"}"#);
-        }
-    };
-
-    match validate_email(pool.clone(), &email).await {
-        Ok(valid) => {
-            if !valid {
-                span.end();
-                return HttpResponse::BadRequest()
-                    .content_type(ContentType::json())
-                    .body(r#"{"detail":"invalid email format"}"#);
-            }
-        }
-        Err(_) => {
-            span.end();
-            return HttpResponse::BadRequest()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"email taken"}"#);
-        }
-    };
-
-    match validate_phone_number(pool.clone(), &phone_number).await {
-        Ok(valid) => {
-            if !valid {
-                span.end();
-                return HttpResponse::BadRequest()
-                    .content_type(ContentType::json())
-                    .body(r#"{"detail":"invalid phone number format"}"#);
-            }
-        }
-        Err(_) => {
-            span.end();
-            return HttpResponse::BadRequest()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"phone number taken"}"#);
-        }
-    };
+    // collect every invalid field so the client can report them all at once
+    let mut errors = ValidationErrors::default();
+
+    validate_username_format(username, &mut errors);
+    validate_email_format(email, &mut errors);
+    let phone_number =
+        &validate_phone_number_format(&req_body.phone_number, &mut errors).unwrap_or_default();
+    validate_password(password, &[username, email], &mut errors);
+
+    if let Err(e) = validate_unique_fields(
+        pool.clone(),
+        None,
+        Some(username),
+        Some(email),
+        Some(phone_number),
+        &mut errors,
+    )
+    .await
+    {
+        span.end();
+        return Err(e);
+    }
 
-    if !validate_password(password.to_string()) {
+    if let Err(e) = errors.into_result() {
         span.end();
-        return HttpResponse::BadRequest()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"invalid password format"}"#);
+        return Err(e);
     }
 
     // create a hash of user password
@@ -126,9 +95,10 @@ pub async fn post_register(
                 e
             );
             span.end();
-            return HttpResponse::InternalServerError()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"an unexpected error occurred"}"#);
+            return Err(AppError::internal(
+                "unexpected_error",
+                "an unexpected error occurred",
+            ));
         }
     };
 
@@ -143,8 +113,11 @@ pub async fn post_register(
                     map.insert("email", user.email);
                     map.insert("phone_number", user.phone_number);
                     map.insert("two_factor_auth", user.two_factor_auth.to_string());
-                    map.insert("profile_pic", user.profile_pic.unwrap_or("".to_string()));
-                    map.insert("bio", user.bio.unwrap_or("".to_string()));
+                    map.insert(
+                        "profile_pic",
+                        user.profile_pic_id.map(media_url).unwrap_or_default(),
+                    );
+                    map.insert("bio", user.bio.unwrap_or_default());
                     map.insert("created_at", user.created_at.to_string());
 
                     let json_str = to_string(&map).unwrap();
@@ -170,11 +143,12 @@ pub async fn post_register(
                         .domain("127.0.0.1")
                         .finish();
 
-                    HttpResponse::Ok()
+                    span.end();
+                    Ok(HttpResponse::Ok()
                         .content_type(ContentType::json())
                         .cookie(access_cookie)
                         .cookie(refresh_cookie)
-                        .body(json_str)
+                        .body(json_str))
                 }
                 Err(e) => {
                     eprintln!(
@@ -184,9 +158,7 @@ pub async fn post_register(
                     );
 
                     span.end();
-                    HttpResponse::Unauthorized()
-                        .content_type(ContentType::json())
-                        .body(r#"{"detail":"incorrect login details"}"#)
+                    Err(e)
                 }
             }
         }
@@ -198,9 +170,7 @@ pub async fn post_register(
             );
 
             span.end();
-            HttpResponse::InternalServerError()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"an unexpected error occurred"}"#)
+            Err(e)
         }
     }
 }
@@ -5,7 +5,9 @@ use crate::logout::post_logout;
 use crate::register::post_register;
 use actix_web::web;
 
-static SCOPE_HANDLERS: &[(&str, fn(&mut web::ServiceConfig))] = &[("auth", routes)];
+type ScopeHandler = (&'static str, fn(&mut web::ServiceConfig));
+
+static SCOPE_HANDLERS: &[ScopeHandler] = &[("auth", routes)];
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg.service(get_csrf)
@@ -0,0 +1,236 @@
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, delete, get, post, web};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use shared::csrf::verify_csrf_token;
+use shared::database::PGPool;
+use shared::error::AppError;
+use shared::friendship::remove_friendship;
+use shared::jwt::{JwtTokenKind, extract_user_id};
+use shared::media::media_url;
+use shared::models::{CreateUserBlock, FriendRequestStatus, PublicUserRow};
+
+#[derive(Deserialize)]
+struct BlockForm {
+    user_id: String,
+}
+
+#[derive(Serialize)]
+struct BlockedUser {
+    id: Uuid,
+    username: String,
+    display_name: Option<String>,
+    profile_pic: Option<String>,
+    blocked_at: DateTime<Utc>,
+}
+
+#[post("/block")]
+pub async fn post_block(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    req_body: web::Json<BlockForm>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: POST /friend/block from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    block_user(pool, &user_id, req_body.user_id.trim()).await
+}
+
+#[delete("/block")]
+pub async fn delete_block(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    req_body: web::Json<BlockForm>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: DELETE /friend/block from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    unblock_user(pool, &user_id, req_body.user_id.trim()).await
+}
+
+#[get("/blocked")]
+pub async fn get_blocked(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /friend/blocked from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let blocked_users = get_blocked_users(pool, &user_id).await?;
+
+    Ok(HttpResponse::Ok().json(blocked_users))
+}
+
+/// Blocks `blocked_id` and, in the same transaction, ends any friendship and
+/// drops pending friend requests between the two users in both directions.
+pub async fn block_user(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+    blocked_id: &str,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::friend_request::dsl as fr;
+    use shared::schema::user_block::dsl as b;
+    use shared::schema::users::dsl as u;
+
+    let mut conn = pool.get().await?;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let blocked_uuid =
+        Uuid::parse_str(blocked_id).map_err(|_| AppError::invalid_uuid("blocked_user_id"))?;
+
+    if blocked_uuid == user_uuid {
+        return Err(AppError::bad_request(
+            "cannot_block_self",
+            "you cannot block yourself",
+        ));
+    }
+
+    let exists = diesel::select(diesel::dsl::exists(
+        u::users
+            .filter(u::id.eq(blocked_uuid))
+            .filter(u::deleted_at.is_null()),
+    ))
+    .get_result::<bool>(&mut conn)
+    .await?;
+
+    if !exists {
+        return Err(AppError::not_found("user_not_found", "user not found"));
+    }
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        async move {
+            diesel::insert_into(b::user_block)
+                .values(CreateUserBlock {
+                    blocker: user_uuid,
+                    blocked: blocked_uuid,
+                })
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .await?;
+
+            remove_friendship(conn, user_uuid, blocked_uuid).await?;
+
+            // declines stay so unblocking does not lift their cooldown; the
+            // sweep removes them once it is over
+            diesel::delete(
+                fr::friend_request
+                    .filter(
+                        fr::requester
+                            .eq(user_uuid)
+                            .and(fr::receiver.eq(blocked_uuid))
+                            .or(fr::requester
+                                .eq(blocked_uuid)
+                                .and(fr::receiver.eq(user_uuid))),
+                    )
+                    .filter(fr::status.ne(FriendRequestStatus::Declined)),
+            )
+            .execute(conn)
+            .await?;
+
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"user blocked successfully"}"#))
+}
+
+pub async fn unblock_user(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+    blocked_id: &str,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::user_block::dsl as b;
+
+    let mut conn = pool.get().await?;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let blocked_uuid =
+        Uuid::parse_str(blocked_id).map_err(|_| AppError::invalid_uuid("blocked_user_id"))?;
+
+    let removed = diesel::delete(
+        b::user_block
+            .filter(b::blocker.eq(user_uuid))
+            .filter(b::blocked.eq(blocked_uuid)),
+    )
+    .execute(&mut conn)
+    .await?;
+
+    if removed == 0 {
+        return Err(AppError::not_found(
+            "block_not_found",
+            "this user is not blocked",
+        ));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"user unblocked successfully"}"#))
+}
+
+async fn get_blocked_users(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+) -> Result<Vec<BlockedUser>, AppError> {
+    use shared::schema::user_block::dsl as b;
+    use shared::schema::users::dsl as u;
+
+    let mut conn = pool.get().await?;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let rows: Vec<(PublicUserRow, DateTime<Utc>)> = b::user_block
+        .inner_join(u::users.on(u::id.eq(b::blocked)))
+        .filter(b::blocker.eq(user_uuid))
+        .order(b::created_at.desc())
+        .select((PublicUserRow::as_select(), b::created_at))
+        .load(&mut conn)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(user, blocked_at)| BlockedUser {
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+            profile_pic: user.profile_pic_id.map(media_url),
+            blocked_at,
+        })
+        .collect())
+}
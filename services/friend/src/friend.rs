@@ -1,13 +1,20 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
 use actix_web::http::header::ContentType;
 use actix_web::web;
-use actix_web::{HttpRequest, HttpResponse, Responder, get, patch, post};
-use chrono::Utc;
-use serde::Deserialize;
+use actix_web::{HttpRequest, HttpResponse, delete, get, patch, post};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
 
 use shared::csrf::verify_csrf_token;
 use shared::database::PGPool;
+use shared::error::AppError;
+use shared::friend_request::FRIEND_REQUEST_POLICY;
 use shared::jwt::{JwtTokenKind, extract_user_id};
-use shared::validate::validate_existing_username;
+use shared::presence::PresenceRedis;
+use shared::username::normalise_username;
+use shared::validate::{ValidationErrors, validate_existing_username};
 
 #[derive(Deserialize)]
 struct AddFriendForm {
@@ -19,6 +26,58 @@ struct RemoveFriendForm {
     removed_friend_id: String,
 }
 
+pub const FRIENDS_DEFAULT_PAGE_SIZE: u32 = 50;
+pub const FRIENDS_MAX_PAGE_SIZE: u32 = 100;
+
+#[derive(Deserialize)]
+struct FriendListQuery {
+    cursor: Option<String>,
+    limit: Option<u32>,
+    #[serde(default)]
+    sort: FriendSort,
+    /// Only friends in this list of the caller's.
+    list: Option<String>,
+    /// Only friends marked as favourites.
+    #[serde(default)]
+    favourites: bool,
+}
+
+/// Order of the friend list: by username, oldest friendships first
+/// (`created_at`) or newest friendships first (`recent`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FriendSort {
+    #[default]
+    Username,
+    CreatedAt,
+    Recent,
+}
+
+impl FriendSort {
+    fn as_str(self) -> &'static str {
+        match self {
+            FriendSort::Username => "username",
+            FriendSort::CreatedAt => "created_at",
+            FriendSort::Recent => "recent",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FriendRequestsQuery {
+    #[serde(default)]
+    direction: RequestDirection,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestDirection {
+    #[default]
+    Incoming,
+    Outgoing,
+    All,
+}
+
 #[derive(Deserialize)]
 struct FriendRequestForm {
     requesting_user_id: String,
@@ -30,26 +89,19 @@ pub async fn post_remove(
     pool: web::Data<PGPool>,
     req: HttpRequest,
     req_body: web::Json<RemoveFriendForm>,
-) -> impl Responder {
+) -> Result<HttpResponse, AppError> {
     println!(
         "{:?}: POST /friend/remove from {:?}",
         Utc::now().timestamp() as usize,
         req.peer_addr()
     );
 
-    let verify_csrf = verify_csrf_token(&req);
-
-    if !verify_csrf {
-        return HttpResponse::Unauthorized()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"csrf failed"}"#);
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
     }
 
     // extract user id from access token
-    let user_id = match extract_user_id(&req, JwtTokenKind::ACCESS) {
-        Ok(id) => id,
-        Err(resp) => return resp,
-    };
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
 
     let removed_friend_id = req_body.removed_friend_id.trim();
 
@@ -57,7 +109,12 @@ pub async fn post_remove(
 }
 
 #[get("/all")]
-pub async fn get_all(pool: web::Data<PGPool>, req: HttpRequest) -> impl Responder {
+pub async fn get_all(
+    pool: web::Data<PGPool>,
+    redis: web::Data<PresenceRedis>,
+    req: HttpRequest,
+    query: web::Query<FriendListQuery>,
+) -> Result<HttpResponse, AppError> {
     println!(
         "{:?}: GET /friend/all from {:?}",
         Utc::now().timestamp() as usize,
@@ -65,19 +122,49 @@ pub async fn get_all(pool: web::Data<PGPool>, req: HttpRequest) -> impl Responde
     );
 
     // extract user id from access token
-    let user_id = match extract_user_id(&req, JwtTokenKind::ACCESS) {
-        Ok(id) => id,
-        Err(resp) => return resp,
-    };
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
 
-    let all_friends_json = match get_all_friends(pool, &user_id).await {
-        Ok(val) => val,
-        Err(_) => "{}".to_string(),
-    };
+    let limit = query.limit.unwrap_or(FRIENDS_DEFAULT_PAGE_SIZE);
 
-    HttpResponse::Ok()
-        .content_type(ContentType::json())
-        .body(all_friends_json)
+    let mut errors = ValidationErrors::default();
+    if limit == 0 || limit > FRIENDS_MAX_PAGE_SIZE {
+        errors.add_length("limit", 1, FRIENDS_MAX_PAGE_SIZE as usize);
+    }
+    errors.into_result()?;
+
+    let mut page = get_friends_page(
+        pool.clone(),
+        &user_id,
+        query.sort,
+        query.cursor.as_deref(),
+        limit,
+        query.list.as_deref(),
+        query.favourites,
+    )
+    .await?;
+
+    add_presence(pool, &redis, &mut page).await?;
+
+    Ok(HttpResponse::Ok().json(page))
+}
+
+#[get("/count")]
+pub async fn get_count(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /friend/count from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let count = count_friends(pool, &user_id).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "count": count })))
 }
 
 #[post("/add")]
@@ -85,36 +172,30 @@ pub async fn post_add(
     pool: web::Data<PGPool>,
     req: HttpRequest,
     req_body: web::Json<AddFriendForm>,
-) -> impl Responder {
+) -> Result<HttpResponse, AppError> {
     println!(
         "{:?}: POST /friend/add from {:?}",
         Utc::now().timestamp() as usize,
         req.peer_addr()
     );
 
-    let verify_csrf = verify_csrf_token(&req);
-
-    if !verify_csrf {
-        return HttpResponse::Unauthorized()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"csrf failed"}"#);
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
     }
 
     // extract user id from access token
-    let user_id = match extract_user_id(&req, JwtTokenKind::ACCESS) {
-        Ok(id) => id,
-        Err(resp) => return resp,
-    };
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
 
-    let username = req_body.username.trim();
+    let username = &normalise_username(&req_body.username);
 
-    if !validate_existing_username(&username) {
-        return HttpResponse::Unauthorized()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"invalid username"}"#);
+    if !validate_existing_username(username) {
+        return Err(AppError::bad_request(
+            "invalid_username",
+            "invalid username",
+        ));
     }
 
-    send_friend_request(pool, &user_id, &username).await
+    send_friend_request(pool, &user_id, username).await
 }
 
 #[patch("/add")]
@@ -122,84 +203,147 @@ pub async fn patch_add(
     pool: web::Data<PGPool>,
     req: HttpRequest,
     req_body: web::Json<FriendRequestForm>,
-) -> impl Responder {
+) -> Result<HttpResponse, AppError> {
     println!(
         "{:?}: PATCH /friend/add from {:?}",
         Utc::now().timestamp() as usize,
         req.peer_addr()
     );
 
-    let verify_csrf = verify_csrf_token(&req);
-
-    if !verify_csrf {
-        return HttpResponse::Unauthorized()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"csrf failed"}"#);
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
     }
 
     // extract user id from access token
-    let responding_user_id = match extract_user_id(&req, JwtTokenKind::ACCESS) {
-        Ok(id) => id,
-        Err(resp) => return resp,
-    };
+    let responding_user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
 
     let requesting_user_id = req_body.requesting_user_id.trim();
     let accept = req_body.accept;
 
-    update_friend_request(pool, &responding_user_id, &requesting_user_id, accept).await
+    update_friend_request(pool, &responding_user_id, requesting_user_id, accept).await
 }
 
 #[get("/requests")]
-pub async fn get_friend_requests(pool: web::Data<PGPool>, req: HttpRequest) -> impl Responder {
-    let user_id = match extract_user_id(&req, JwtTokenKind::ACCESS) {
-        Ok(id) => id,
-        Err(resp) => return resp,
-    };
+pub async fn get_friend_requests(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    query: web::Query<FriendRequestsQuery>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /friend/requests from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
 
-    match get_all_friend_requests(pool, &user_id).await {
-        Ok(requests) => HttpResponse::Ok()
-            .content_type(ContentType::json())
-            .json(requests),
-        Err(_) => HttpResponse::InternalServerError()
-            .content_type(ContentType::json())
-            .body("failed to get friend requests"),
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let requests = get_all_friend_requests(pool, &user_id, query.direction).await?;
+
+    Ok(HttpResponse::Ok().json(requests))
+}
+
+#[delete("/requests/{receiver_id}")]
+pub async fn delete_friend_request(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: DELETE /friend/requests from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
     }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    cancel_friend_request(pool, &user_id, path.trim()).await
 }
 
+use diesel::dsl::exists;
 use diesel::prelude::*;
-use diesel::result::{DatabaseErrorKind as DieselDbError, Error as DieselError};
-use diesel_async::RunQueryDsl;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use uuid::Uuid;
 
-use shared::models::{CreateFriend, User};
+use shared::block::has_blocked;
+use shared::cursor::{Cursor, decode_cursor, encode_cursor};
+use shared::friendship::{add_friendship, are_friends, friend_ids, remove_friendship};
+use shared::models::{FriendRequestStatus, PublicUserRow};
+use shared::presence::Presence;
+use shared::profile::{PublicUser, resolve_username};
 use shared::schema::friend_request::dsl::friend_request;
 use shared::schema::users;
+use shared::settings::{FriendRequestsFrom, load_settings};
+
+use crate::lists::find_owned_list;
+use crate::presence::visible_presence;
 
 #[derive(Debug)]
 pub enum AddFriendResult {
     Created,
+    /// The receiver had already asked the requester, so the friendship was
+    /// created instead of a second request.
+    Accepted,
     AlreadyExists,
     AlreadyFriends,
+    NotAllowed,
+    Blocked,
+    /// The receiver declined the last request and the decline cooldown has
+    /// `retry_after` seconds left.
+    Cooldown {
+        retry_after: u64,
+    },
+    /// The requester used up their daily requests; the oldest one counted
+    /// falls out of the window in `retry_after` seconds.
+    LimitReached {
+        retry_after: u64,
+    },
 }
 
 pub async fn send_friend_request(
     pool: web::Data<PGPool>,
     requesting_user_id: &str,
     receiver_username: &str,
-) -> HttpResponse {
-    match add_friend_request_to_db(pool, requesting_user_id, receiver_username).await {
-        Ok(AddFriendResult::Created) => HttpResponse::Ok()
+) -> Result<HttpResponse, AppError> {
+    match add_friend_request_to_db(pool, requesting_user_id, receiver_username).await? {
+        AddFriendResult::Created => Ok(HttpResponse::Ok()
             .content_type(ContentType::json())
-            .body(r#"{"detail":"friend request sent successfully"}"#),
-        Ok(AddFriendResult::AlreadyExists) => HttpResponse::BadRequest()
+            .body(r#"{"detail":"friend request sent successfully"}"#)),
+        AddFriendResult::Accepted => Ok(HttpResponse::Ok()
             .content_type(ContentType::json())
-            .body(r#"{"detail":"friend request already exists"}"#),
-        Ok(AddFriendResult::AlreadyFriends) => HttpResponse::BadRequest()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"already friends with this user"}"#),
-        Err(_) => HttpResponse::InternalServerError()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"could not send friend request"}"#),
+            .body(r#"{"detail":"friend request accepted"}"#)),
+        AddFriendResult::AlreadyExists => Err(AppError::conflict(
+            "friend_request_exists",
+            "friend request already exists",
+        )),
+        AddFriendResult::AlreadyFriends => Err(AppError::conflict(
+            "already_friends",
+            "already friends with this user",
+        )),
+        AddFriendResult::Blocked => Err(AppError::conflict(
+            "user_blocked",
+            "unblock this user before sending a friend request",
+        )),
+        AddFriendResult::NotAllowed => Err(AppError::forbidden(
+            "friend_requests_not_allowed",
+            "this user does not accept friend requests from you",
+        )),
+        AddFriendResult::Cooldown { retry_after } => Err(AppError::too_many_requests(
+            "friend_request_cooldown",
+            "you cannot send this user another friend request yet",
+            retry_after,
+        )),
+        AddFriendResult::LimitReached { retry_after } => Err(AppError::too_many_requests(
+            "friend_request_limit",
+            "too many friend requests sent today",
+            retry_after,
+        )),
     }
 }
 
@@ -207,311 +351,632 @@ pub async fn add_friend_request_to_db(
     pool: web::Data<PGPool>,
     requesting_id: &str,
     responding_username: &str,
-) -> Result<AddFriendResult, DieselError> {
+) -> Result<AddFriendResult, AppError> {
     use diesel::insert_into;
-    use shared::models::{CreateFriendRequest, User};
-    use shared::schema::friend::dsl as f;
+    use shared::models::CreateFriendRequest;
+    use shared::schema::friend_request::dsl as fr;
+    use shared::schema::friend_request_send::dsl as frs;
     use shared::schema::users::dsl as u;
 
-    let mut conn = pool.get().await.map_err(|e| {
-        eprintln!("{}: Failed to get DB connection: {:?}", Utc::now(), e);
-        DieselError::NotFound // Ideally map to a proper custom error
-    })?;
+    let mut conn = pool.get().await?;
 
-    let user_uuid = Uuid::parse_str(requesting_id).map_err(|e| {
-        eprintln!("{}: Invalid user UUID: {:?}", Utc::now(), e);
-        DieselError::NotFound
-    })?;
+    let user_uuid =
+        Uuid::parse_str(requesting_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
 
-    let receiver_user: User = u::users
-        .filter(u::username.ilike(responding_username))
-        .first::<User>(&mut conn)
-        .await?;
+    // former usernames still resolve to their owner during the hold period
+    let receiver_id = resolve_username(&mut conn, responding_username)
+        .await?
+        .ok_or_else(|| AppError::not_found("user_not_found", "user not found"))?;
 
-    if receiver_user.id == user_uuid {
+    if receiver_id == user_uuid {
         return Ok(AddFriendResult::AlreadyExists); // Cannot friend yourself
     }
 
-    let is_already_friends = match f::friend
-        .filter(
-            f::user1
-                .eq(user_uuid)
-                .and(f::user2.eq(receiver_user.id))
-                .or(f::user1.eq(receiver_user.id).and(f::user2.eq(user_uuid))),
+    if has_blocked(&mut conn, user_uuid, receiver_id).await? {
+        return Ok(AddFriendResult::Blocked);
+    }
+
+    // users who blocked the requester are hidden from them entirely
+    if has_blocked(&mut conn, receiver_id, user_uuid).await? {
+        return Err(AppError::not_found("user_not_found", "user not found"));
+    }
+
+    if are_friends(&mut conn, user_uuid, receiver_id).await? {
+        return Ok(AddFriendResult::AlreadyFriends);
+    }
+
+    let policy = &*FRIEND_REQUEST_POLICY;
+    let now = Utc::now();
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        async move {
+            // lock both users in a fixed order so two requests crossing each
+            // other are serialised and the second one sees the first
+            u::users
+                .filter(u::id.eq_any([user_uuid, receiver_id]))
+                .order(u::id)
+                .select(u::id)
+                .for_update()
+                .load::<Uuid>(conn)
+                .await?;
+
+            // the receiver already asked us, so accept their request and
+            // become friends instead of leaving two requests pending
+            let crossed = diesel::update(
+                friend_request
+                    .filter(fr::requester.eq(receiver_id))
+                    .filter(fr::receiver.eq(user_uuid))
+                    .filter(fr::status.eq(FriendRequestStatus::Pending))
+                    .filter(fr::created_at.ge(now - policy.expiry)),
+            )
+            .set((
+                fr::status.eq(FriendRequestStatus::Accepted),
+                fr::responded_at.eq(Utc::now()),
+            ))
+            .execute(conn)
+            .await?;
+
+            if crossed > 0 {
+                add_friendship(conn, user_uuid, receiver_id).await?;
+
+                return Ok(AddFriendResult::Accepted);
+            }
+
+            let receiver_settings = load_settings(conn, receiver_id).await?;
+
+            let allowed = match receiver_settings.privacy.friend_requests_from {
+                FriendRequestsFrom::Everyone => true,
+                FriendRequestsFrom::FriendsOfFriends => {
+                    have_mutual_friend(conn, user_uuid, receiver_id).await?
+                }
+                FriendRequestsFrom::Nobody => false,
+            };
+
+            if !allowed {
+                return Ok(AddFriendResult::NotAllowed);
+            }
+
+            let previous: Option<(FriendRequestStatus, DateTime<Utc>, Option<DateTime<Utc>>)> =
+                friend_request
+                    .filter(fr::requester.eq(user_uuid))
+                    .filter(fr::receiver.eq(receiver_id))
+                    .select((fr::status, fr::created_at, fr::responded_at))
+                    .first(conn)
+                    .await
+                    .optional()?;
+
+            match previous {
+                // a pending request past its expiry counts as gone even before
+                // the sweep removes it, and is reopened below
+                Some((FriendRequestStatus::Pending, created_at, _))
+                    if created_at >= now - policy.expiry =>
+                {
+                    return Ok(AddFriendResult::AlreadyExists);
+                }
+                Some((FriendRequestStatus::Declined, _, Some(responded_at)))
+                    if responded_at + policy.decline_cooldown > now =>
+                {
+                    return Ok(AddFriendResult::Cooldown {
+                        retry_after: seconds_until(responded_at + policy.decline_cooldown),
+                    });
+                }
+                _ => {}
+            }
+
+            let window_start = now - TimeDelta::days(1);
+
+            // counted from the send log, which outlives cancelled requests
+            let sent_today: Vec<DateTime<Utc>> = frs::friend_request_send
+                .filter(frs::requester.eq(user_uuid))
+                .filter(frs::sent_at.gt(window_start))
+                .order(frs::sent_at.asc())
+                .select(frs::sent_at)
+                .limit(policy.daily_limit)
+                .load(conn)
+                .await?;
+
+            if sent_today.len() as i64 >= policy.daily_limit {
+                return Ok(AddFriendResult::LimitReached {
+                    retry_after: sent_today
+                        .iter()
+                        .min()
+                        .map_or(0, |oldest| seconds_until(*oldest + TimeDelta::days(1))),
+                });
+            }
+
+            match previous {
+                // an answered or expired request from before is reopened
+                Some(_) => {
+                    diesel::update(
+                        friend_request
+                            .filter(fr::requester.eq(user_uuid))
+                            .filter(fr::receiver.eq(receiver_id)),
+                    )
+                    .set((
+                        fr::status.eq(FriendRequestStatus::Pending),
+                        fr::created_at.eq(now),
+                        fr::responded_at.eq(None::<DateTime<Utc>>),
+                    ))
+                    .execute(conn)
+                    .await?;
+                }
+                None => {
+                    insert_into(friend_request)
+                        .values(CreateFriendRequest {
+                            requester: user_uuid,
+                            receiver: receiver_id,
+                        })
+                        .execute(conn)
+                        .await?;
+                }
+            }
+
+            insert_into(frs::friend_request_send)
+                .values((frs::requester.eq(user_uuid), frs::sent_at.eq(now)))
+                .execute(conn)
+                .await?;
+
+            Ok(AddFriendResult::Created)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Whole seconds from now until `at`, rounded up and never negative.
+fn seconds_until(at: DateTime<Utc>) -> u64 {
+    let millis = (at - Utc::now()).num_milliseconds().max(0) as u64;
+    millis.div_ceil(1000)
+}
+
+/// Whether `user_a` and `user_b` share at least one friend.
+async fn have_mutual_friend(
+    conn: &mut AsyncPgConnection,
+    user_a: Uuid,
+    user_b: Uuid,
+) -> QueryResult<bool> {
+    let friends_of_a: HashSet<Uuid> = friend_ids(conn, user_a).await?.into_iter().collect();
+    let friends_of_b = friend_ids(conn, user_b).await?;
+
+    Ok(friends_of_b.iter().any(|id| friends_of_a.contains(id)))
+}
+
+/// What the friend list shows of each friend.
+#[derive(Serialize)]
+pub struct FriendEntry {
+    #[serde(flatten)]
+    pub user: PublicUser,
+    pub friends_since: DateTime<Utc>,
+    pub favourite: bool,
+    /// The caller's private nickname for this friend.
+    pub nickname: Option<String>,
+    /// Absent while presence is unavailable.
+    pub presence: Option<Presence>,
+}
+
+type FriendRow = (PublicUserRow, DateTime<Utc>, Option<bool>, Option<String>);
+
+#[derive(Serialize)]
+pub struct FriendPage {
+    pub friends: Vec<FriendEntry>,
+    /// Pass back as `cursor` for the next page; absent on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// One page of the friends of `fetching_user_id`, after `cursor` in `sort`
+/// order. Ties are broken by user id so every row has a stable position.
+pub async fn get_friends_page(
+    pool: web::Data<PGPool>,
+    fetching_user_id: &str,
+    sort: FriendSort,
+    cursor: Option<&str>,
+    limit: u32,
+    list: Option<&str>,
+    favourites_only: bool,
+) -> Result<FriendPage, AppError> {
+    use shared::schema::friend::dsl as f;
+    use shared::schema::friend_list_member::dsl as lm;
+    use shared::schema::friend_meta::dsl as m;
+    use shared::schema::users::dsl as u;
+
+    let user_uuid =
+        Uuid::parse_str(fetching_user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let mut conn = pool.get().await?;
+
+    let mut query = u::users
+        .inner_join(
+            f::friend.on(u::id
+                .eq(f::user2)
+                .and(f::user1.eq(user_uuid))
+                .or(u::id.eq(f::user1).and(f::user2.eq(user_uuid)))),
         )
-        .select(f::user1)
-        .first::<Uuid>(&mut conn)
-        .await
-    {
-        Ok(_) => true,
-        Err(DieselError::NotFound) => false,
-        Err(e) => return Err(e.into()),
+        .left_join(m::friend_meta.on(m::user_id.eq(user_uuid).and(m::friend_id.eq(u::id))))
+        .filter(u::deleted_at.is_null())
+        .select((
+            PublicUserRow::as_select(),
+            f::created_at,
+            m::favourite.nullable(),
+            m::nickname.nullable(),
+        ))
+        .into_boxed();
+
+    if let Some(list) = list {
+        let list_uuid = find_owned_list(&mut conn, user_uuid, list).await?;
+
+        query = query.filter(exists(
+            lm::friend_list_member
+                .filter(lm::list_id.eq(list_uuid))
+                .filter(lm::friend_id.eq(u::id)),
+        ));
+    }
+
+    if favourites_only {
+        query = query.filter(m::favourite.nullable().eq(true));
+    }
+
+    query = match sort {
+        FriendSort::Username => query.order((u::username_normalised.asc(), u::id.asc())),
+        FriendSort::CreatedAt => query.order((f::created_at.asc(), u::id.asc())),
+        FriendSort::Recent => query.order((f::created_at.desc(), u::id.desc())),
     };
 
-    if is_already_friends {
-        return Ok(AddFriendResult::AlreadyFriends);
+    if let Some(cursor) = cursor {
+        let Cursor { key, id } = decode_cursor(cursor, sort.as_str())?;
+
+        query = match sort {
+            FriendSort::Username => query.filter(
+                u::username_normalised
+                    .gt(key.clone())
+                    .or(u::username_normalised.eq(key).and(u::id.gt(id))),
+            ),
+            FriendSort::CreatedAt | FriendSort::Recent => {
+                let since = key
+                    .parse()
+                    .ok()
+                    .and_then(DateTime::from_timestamp_micros)
+                    .ok_or_else(|| AppError::bad_request("invalid_cursor", "invalid cursor"))?;
+
+                if sort == FriendSort::CreatedAt {
+                    query.filter(
+                        f::created_at
+                            .gt(since)
+                            .or(f::created_at.eq(since).and(u::id.gt(id))),
+                    )
+                } else {
+                    query.filter(
+                        f::created_at
+                            .lt(since)
+                            .or(f::created_at.eq(since).and(u::id.lt(id))),
+                    )
+                }
+            }
+        };
     }
 
-    let new_friend_request = vec![CreateFriendRequest {
-        requester: user_uuid,
-        receiver: receiver_user.id,
-    }];
+    let mut rows: Vec<FriendRow> = query.limit(i64::from(limit) + 1).load(&mut conn).await?;
+
+    let has_more = rows.len() > limit as usize;
+    rows.truncate(limit as usize);
+
+    let next_cursor = rows
+        .last()
+        .filter(|_| has_more)
+        .map(|(user, friends_since, _, _)| {
+            let key = match sort {
+                FriendSort::Username => user.username_normalised.clone(),
+                FriendSort::CreatedAt | FriendSort::Recent => {
+                    friends_since.timestamp_micros().to_string()
+                }
+            };
+
+            encode_cursor(sort.as_str(), &key, user.id)
+        });
+
+    Ok(FriendPage {
+        friends: rows
+            .into_iter()
+            .map(|(user, friends_since, favourite, nickname)| FriendEntry {
+                user: user.into(),
+                friends_since,
+                favourite: favourite.unwrap_or(false),
+                nickname,
+                presence: None,
+            })
+            .collect(),
+        next_cursor,
+    })
+}
 
-    let result = insert_into(friend_request)
-        .values(&new_friend_request)
-        .on_conflict_do_nothing()
-        .execute(&mut conn)
-        .await?;
+/// Fills in the presence of every friend on `page`, leaving it out while redis
+/// is down.
+pub async fn add_presence(
+    pool: web::Data<PGPool>,
+    redis: &PresenceRedis,
+    page: &mut FriendPage,
+) -> Result<(), AppError> {
+    let ids: Vec<Uuid> = page.friends.iter().map(|entry| entry.user.id).collect();
 
-    if result > 0 {
-        Ok(AddFriendResult::Created)
-    } else {
-        Ok(AddFriendResult::AlreadyExists)
+    let mut conn = pool.get().await?;
+
+    if let Some(mut presence) = visible_presence(&mut conn, redis, &ids).await? {
+        for entry in &mut page.friends {
+            entry.presence = presence.remove(&entry.user.id);
+        }
     }
+
+    Ok(())
 }
 
-pub async fn get_all_friends(
+pub async fn count_friends(
     pool: web::Data<PGPool>,
     fetching_user_id: &str,
-) -> Result<String, DieselError> {
-    use shared::models::User;
-    use shared::schema::{friend, users};
-
-    let mut conn = pool.get().await.map_err(|e| {
-        eprintln!(
-            "{:?}: Failed to acquire DB connection: {:?}",
-            Utc::now().timestamp() as usize,
-            e
-        );
-        DieselError::DatabaseError(DieselDbError::UnableToSendCommand, Box::new(e.to_string()))
-    })?;
-
-    let user_uuid = Uuid::parse_str(&fetching_user_id.to_string()).map_err(|e| {
-        eprintln!(
-            "{:?}: Failed to acquire DB connection: {:?}",
-            Utc::now().timestamp() as usize,
-            e
-        );
-        DieselError::DatabaseError(DieselDbError::UnableToSendCommand, Box::new(e.to_string()))
-    })?;
+) -> Result<i64, AppError> {
+    use shared::schema::friend::dsl as f;
+    use shared::schema::users::dsl as u;
+
+    let mut conn = pool.get().await?;
 
-    let results: Vec<User> = users::table
+    let user_uuid =
+        Uuid::parse_str(fetching_user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let count = u::users
         .inner_join(
-            friend::table.on(users::id
-                .eq(friend::user1)
-                .and(friend::user2.eq(user_uuid))
-                .or(users::id.eq(friend::user2).and(friend::user1.eq(user_uuid)))),
+            f::friend.on(u::id
+                .eq(f::user2)
+                .and(f::user1.eq(user_uuid))
+                .or(u::id.eq(f::user1).and(f::user2.eq(user_uuid)))),
         )
-        .select(users::all_columns)
-        .distinct()
-        .load(&mut conn)
+        .filter(u::deleted_at.is_null())
+        .count()
+        .get_result(&mut conn)
         .await?;
 
-    serde_json::to_string_pretty(&results).map_err(|e| {
-        eprintln!("JSON serialization error: {:?}", e);
-        DieselError::SerializationError(Box::new(e))
-    })
+    Ok(count)
+}
+
+/// A pending friend request as listed to one of its two users: the other user,
+/// which way the request goes and when it was sent.
+#[derive(Serialize)]
+pub struct FriendRequestEntry {
+    #[serde(flatten)]
+    pub user: PublicUser,
+    pub direction: RequestDirection,
+    pub created_at: DateTime<Utc>,
 }
 
 pub async fn get_all_friend_requests(
     pool: web::Data<PGPool>,
     user_id: &str,
-) -> Result<String, DieselError> {
+    direction: RequestDirection,
+) -> Result<Vec<FriendRequestEntry>, AppError> {
     use shared::schema::friend_request::dsl as fr;
     use shared::schema::users::dsl as u;
 
-    let mut conn = pool.get().await.map_err(|e| {
-        eprintln!(
-            "{:?}: Failed to acquire DB connection: {:?}",
-            Utc::now().timestamp() as usize,
-            e
+    let mut conn = pool.get().await?;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    // expired requests are hidden before the sweep gets to them
+    let expired_before = Utc::now() - FRIEND_REQUEST_POLICY.expiry;
+
+    let mut results: Vec<FriendRequestEntry> = Vec::new();
+
+    if direction != RequestDirection::Outgoing {
+        let incoming: Vec<(PublicUserRow, DateTime<Utc>)> = u::users
+            .inner_join(friend_request.on(fr::requester.eq(users::id)))
+            .filter(fr::receiver.eq(user_uuid))
+            .filter(fr::status.eq(FriendRequestStatus::Pending))
+            .filter(fr::created_at.ge(expired_before))
+            .select((PublicUserRow::as_select(), fr::created_at))
+            .load(&mut conn)
+            .await?;
+
+        results.extend(
+            incoming
+                .into_iter()
+                .map(|(user, created_at)| FriendRequestEntry {
+                    user: user.into(),
+                    direction: RequestDirection::Incoming,
+                    created_at,
+                }),
         );
-        DieselError::DatabaseError(DieselDbError::UnableToSendCommand, Box::new(e.to_string()))
-    })?;
+    }
 
-    let user_uuid = Uuid::parse_str(user_id).map_err(|_| DieselError::NotFound)?;
+    if direction != RequestDirection::Incoming {
+        let outgoing: Vec<(PublicUserRow, DateTime<Utc>)> = u::users
+            .inner_join(friend_request.on(fr::receiver.eq(users::id)))
+            .filter(fr::requester.eq(user_uuid))
+            .filter(fr::status.eq(FriendRequestStatus::Pending))
+            .filter(fr::created_at.ge(expired_before))
+            .select((PublicUserRow::as_select(), fr::created_at))
+            .load(&mut conn)
+            .await?;
+
+        results.extend(
+            outgoing
+                .into_iter()
+                .map(|(user, created_at)| FriendRequestEntry {
+                    user: user.into(),
+                    direction: RequestDirection::Outgoing,
+                    created_at,
+                }),
+        );
+    }
 
-    let results: Vec<User> = u::users
-        .inner_join(friend_request.on(fr::requester.eq(users::id)))
-        .filter(fr::receiver.eq(user_uuid))
-        .select(users::all_columns)
-        .load(&mut conn)
-        .await?;
+    // newest first across both directions
+    results.sort_by_key(|entry| Reverse(entry.created_at));
 
-    serde_json::to_string_pretty(&results).map_err(|e| {
-        eprintln!("JSON serialization error: {:?}", e);
-        DieselError::SerializationError(Box::new(e))
-    })
+    Ok(results)
 }
 
-pub async fn remove_friend(
+/// Withdraws a pending request `requesting_user_id` sent to `receiver_id`.
+pub async fn cancel_friend_request(
     pool: web::Data<PGPool>,
-    user_id: &str,
-    removed_friend_id: &str,
-) -> HttpResponse {
-    use shared::schema::friend::dsl as f;
+    requesting_user_id: &str,
+    receiver_id: &str,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::friend_request::dsl as fr;
 
-    let mut conn = match pool.get().await {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!(
-                "{}: Failed to acquire DB connection: {:?}",
-                Utc::now().timestamp(),
-                e
-            );
-            return HttpResponse::InternalServerError()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"Database connection error"}"#);
-        }
-    };
+    let mut conn = pool.get().await?;
 
-    let user_uuid = match Uuid::parse_str(user_id) {
-        Ok(uuid) => uuid,
-        Err(e) => {
-            eprintln!("{}: Invalid user_id UUID: {:?}", Utc::now().timestamp(), e);
-            return HttpResponse::BadRequest()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"Invalid user_id UUID"}"#);
-        }
-    };
+    let requesting_uuid = Uuid::parse_str(requesting_user_id)
+        .map_err(|_| AppError::invalid_uuid("requesting_user_id"))?;
 
-    let removed_friend_uuid = match Uuid::parse_str(removed_friend_id) {
-        Ok(uuid) => uuid,
-        Err(e) => {
-            eprintln!(
-                "{}: Invalid removed_friend_id UUID: {:?}",
-                Utc::now().timestamp(),
-                e
-            );
-            return HttpResponse::BadRequest()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"Invalid removed_friend_id UUID"}"#);
-        }
-    };
+    let receiver_uuid =
+        Uuid::parse_str(receiver_id).map_err(|_| AppError::invalid_uuid("receiver_id"))?;
 
-    let rows_deleted = diesel::delete(
-        f::friend.filter(
-            f::user1
-                .eq(user_uuid)
-                .and(f::user2.eq(removed_friend_uuid))
-                .or(f::user1.eq(removed_friend_uuid).and(f::user2.eq(user_uuid))),
-        ),
+    let removed = diesel::delete(
+        friend_request
+            .filter(fr::requester.eq(requesting_uuid))
+            .filter(fr::receiver.eq(receiver_uuid))
+            .filter(fr::status.eq(FriendRequestStatus::Pending))
+            .filter(fr::created_at.ge(Utc::now() - FRIEND_REQUEST_POLICY.expiry)),
     )
     .execute(&mut conn)
-    .await;
+    .await?;
 
-    match rows_deleted {
-        Ok(_) => HttpResponse::Ok()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"friend removed successfully"}"#),
-        Err(e) => {
-            eprintln!("DB insert error: {:?}", e);
-            HttpResponse::InternalServerError()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"internal server error"}"#)
-        }
+    if removed == 0 {
+        return Err(AppError::not_found(
+            "friend_request_not_found",
+            "friend request not found",
+        ));
     }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"friend request cancelled"}"#))
 }
 
+pub async fn remove_friend(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+    removed_friend_id: &str,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::friend_request::dsl as fr;
+
+    let mut conn = pool.get().await?;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let removed_friend_uuid = Uuid::parse_str(removed_friend_id)
+        .map_err(|_| AppError::invalid_uuid("removed_friend_id"))?;
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        async move {
+            remove_friendship(conn, user_uuid, removed_friend_uuid).await?;
+
+            // the answered request that made them friends goes too, so either
+            // user can send a fresh one later
+            diesel::delete(
+                friend_request.filter(
+                    fr::requester
+                        .eq(user_uuid)
+                        .and(fr::receiver.eq(removed_friend_uuid))
+                        .or(fr::requester
+                            .eq(removed_friend_uuid)
+                            .and(fr::receiver.eq(user_uuid))),
+                ),
+            )
+            .execute(conn)
+            .await?;
+
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"friend removed successfully"}"#))
+}
+
+/// Answers the request `requesting_user_id` sent to `responding_user_id`. The
+/// answer is recorded on the request, so repeating it is a no-op that returns
+/// the same response, while changing it afterwards is a conflict.
 pub async fn update_friend_request(
     pool: web::Data<PGPool>,
     responding_user_id: &str,
     requesting_user_id: &str,
     accept: bool,
-) -> HttpResponse {
-    use shared::schema::friend::dsl as f;
+) -> Result<HttpResponse, AppError> {
     use shared::schema::friend_request::dsl as fr;
 
-    let mut conn = match pool.get().await {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!(
-                "{}: Failed to acquire DB connection: {:?}",
-                Utc::now().timestamp(),
-                e
-            );
-            return HttpResponse::InternalServerError()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"Database connection error"}"#);
-        }
-    };
+    let mut conn = pool.get().await?;
 
-    let responding_uuid = match Uuid::parse_str(responding_user_id) {
-        Ok(uuid) => uuid,
-        Err(e) => {
-            eprintln!(
-                "{}: Invalid responding_user_id UUID: {:?}",
-                Utc::now().timestamp(),
-                e
-            );
-            return HttpResponse::BadRequest()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"Invalid responding_user_id UUID"}"#);
-        }
-    };
+    let responding_uuid = Uuid::parse_str(responding_user_id)
+        .map_err(|_| AppError::invalid_uuid("responding_user_id"))?;
 
-    let requesting_uuid = match Uuid::parse_str(requesting_user_id) {
-        Ok(uuid) => uuid,
-        Err(e) => {
-            eprintln!(
-                "{}: Invalid requesting_user_id UUID: {:?}",
-                Utc::now().timestamp(),
-                e
-            );
-            return HttpResponse::BadRequest()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"Invalid requesting_user_id UUID"}"#);
-        }
-    };
+    let requesting_uuid = Uuid::parse_str(requesting_user_id)
+        .map_err(|_| AppError::invalid_uuid("requesting_user_id"))?;
 
-    let rows_deleted = diesel::delete(
-        friend_request.filter(
-            fr::receiver
-                .eq(responding_uuid)
-                .and(fr::requester.eq(requesting_uuid)),
-        ),
-    )
-    .execute(&mut conn)
-    .await;
-
-    match rows_deleted {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("DB insert error: {:?}", e);
-            return HttpResponse::InternalServerError()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"internal server error"}"#);
-        }
-    }
+    let answer = if accept {
+        FriendRequestStatus::Accepted
+    } else {
+        FriendRequestStatus::Declined
+    };
 
-    if accept {
-        let new_friend = CreateFriend {
-            user1: requesting_uuid,
-            user2: responding_uuid,
-        };
+    conn.transaction::<_, AppError, _>(|conn| {
+        async move {
+            let request: Option<(FriendRequestStatus, DateTime<Utc>)> = friend_request
+                .filter(fr::requester.eq(requesting_uuid))
+                .filter(fr::receiver.eq(responding_uuid))
+                .select((fr::status, fr::created_at))
+                .for_update()
+                .first(conn)
+                .await
+                .optional()?;
+
+            let expired_before = Utc::now() - FRIEND_REQUEST_POLICY.expiry;
+
+            match request {
+                Some((FriendRequestStatus::Pending, created_at))
+                    if created_at >= expired_before => {}
+                // an expired request is as good as gone, even before the sweep
+                // removes it
+                None | Some((FriendRequestStatus::Pending, _)) => {
+                    return Err(AppError::not_found(
+                        "friend_request_not_found",
+                        "friend request not found",
+                    ));
+                }
+                // a retry of the answer already given
+                Some((status, _)) if status == answer => return Ok(()),
+                Some(_) => {
+                    return Err(AppError::conflict(
+                        "friend_request_answered",
+                        "friend request has already been answered",
+                    ));
+                }
+            }
 
-        let rows_inserted = diesel::insert_into(f::friend)
-            .values(&new_friend)
-            .execute(&mut conn)
-            .await;
-
-        match rows_inserted {
-            Ok(n) if n > 0 => HttpResponse::Ok()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"friend request accepted"}"#),
-            Ok(_) => HttpResponse::InternalServerError()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"failed to accept friend request"}"#),
-            Err(e) => {
-                eprintln!("DB insert error: {:?}", e);
-                HttpResponse::InternalServerError()
-                    .content_type(ContentType::json())
-                    .body(r#"{"detail":"internal server error"}"#)
+            diesel::update(
+                friend_request
+                    .filter(fr::requester.eq(requesting_uuid))
+                    .filter(fr::receiver.eq(responding_uuid)),
+            )
+            .set((fr::status.eq(answer), fr::responded_at.eq(Utc::now())))
+            .execute(conn)
+            .await?;
+
+            if accept {
+                add_friendship(conn, requesting_uuid, responding_uuid).await?;
             }
+
+            Ok(())
         }
+        .scope_boxed()
+    })
+    .await?;
+
+    if accept {
+        Ok(HttpResponse::Ok()
+            .content_type(ContentType::json())
+            .body(r#"{"detail":"friend request accepted"}"#))
     } else {
-        HttpResponse::Ok()
+        Ok(HttpResponse::Ok()
             .content_type(ContentType::json())
-            .body(r#"{"detail":"friend request declined"}"#)
+            .body(r#"{"detail":"friend request declined"}"#))
     }
 }
@@ -0,0 +1,390 @@
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, delete, get, patch, post, put, web};
+use chrono::Utc;
+use diesel::dsl::count;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use shared::csrf::verify_csrf_token;
+use shared::database::PGPool;
+use shared::error::AppError;
+use shared::friendship::are_friends;
+use shared::jwt::{JwtTokenKind, extract_user_id};
+use shared::models::{CreateFriendList, CreateFriendListMember, FriendList};
+use shared::validate::{ValidationErrors, validate_friend_list_name};
+
+/// Most lists a single user may have.
+pub const MAX_FRIEND_LISTS: i64 = 50;
+
+#[derive(Deserialize)]
+struct FriendListForm {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct FriendListSummary {
+    #[serde(flatten)]
+    list: FriendList,
+    member_count: i64,
+}
+
+#[get("/lists")]
+pub async fn get_lists(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /friend/lists from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let lists = get_friend_lists(pool, &user_id).await?;
+
+    Ok(HttpResponse::Ok().json(lists))
+}
+
+#[post("/lists")]
+pub async fn post_list(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    req_body: web::Json<FriendListForm>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: POST /friend/lists from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let list = create_friend_list(pool, &user_id, req_body.name.trim()).await?;
+
+    Ok(HttpResponse::Created().json(list))
+}
+
+#[patch("/lists/{list_id}")]
+pub async fn patch_list(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    req_body: web::Json<FriendListForm>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: PATCH /friend/lists from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let list = rename_friend_list(pool, &user_id, path.trim(), req_body.name.trim()).await?;
+
+    Ok(HttpResponse::Ok().json(list))
+}
+
+#[delete("/lists/{list_id}")]
+pub async fn delete_list(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: DELETE /friend/lists from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    delete_friend_list(pool, &user_id, path.trim()).await
+}
+
+#[put("/lists/{list_id}/members/{friend_id}")]
+pub async fn put_list_member(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: PUT /friend/lists/members from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let (list_id, friend_id) = path.into_inner();
+
+    add_list_member(pool, &user_id, list_id.trim(), friend_id.trim()).await
+}
+
+#[delete("/lists/{list_id}/members/{friend_id}")]
+pub async fn delete_list_member(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: DELETE /friend/lists/members from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let (list_id, friend_id) = path.into_inner();
+
+    remove_list_member(pool, &user_id, list_id.trim(), friend_id.trim()).await
+}
+
+/// The id of list `list_id` if `owner` owns it. Lists of other users are
+/// reported as missing rather than forbidden.
+pub async fn find_owned_list(
+    conn: &mut AsyncPgConnection,
+    owner: Uuid,
+    list_id: &str,
+) -> Result<Uuid, AppError> {
+    use shared::schema::friend_list::dsl as l;
+
+    let list_uuid = Uuid::parse_str(list_id).map_err(|_| AppError::invalid_uuid("list_id"))?;
+
+    l::friend_list
+        .filter(l::id.eq(list_uuid))
+        .filter(l::owner_id.eq(owner))
+        .select(l::id)
+        .first(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::not_found("friend_list_not_found", "friend list not found"))
+}
+
+/// List names are unique per user, ignoring case.
+fn list_name_taken(e: diesel::result::Error) -> AppError {
+    match AppError::from(e) {
+        AppError::Conflict { .. } => AppError::conflict(
+            "friend_list_exists",
+            "you already have a list with this name",
+        ),
+        e => e,
+    }
+}
+
+async fn get_friend_lists(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+) -> Result<Vec<FriendListSummary>, AppError> {
+    use shared::schema::friend_list::dsl as l;
+    use shared::schema::friend_list_member::dsl as lm;
+
+    let mut conn = pool.get().await?;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let lists: Vec<(FriendList, i64)> = l::friend_list
+        .left_join(lm::friend_list_member)
+        .filter(l::owner_id.eq(user_uuid))
+        .group_by(l::id)
+        .order(l::name.asc())
+        .select((FriendList::as_select(), count(lm::friend_id.nullable())))
+        .load(&mut conn)
+        .await?;
+
+    Ok(lists
+        .into_iter()
+        .map(|(list, member_count)| FriendListSummary { list, member_count })
+        .collect())
+}
+
+async fn create_friend_list(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+    name: &str,
+) -> Result<FriendList, AppError> {
+    use shared::schema::friend_list::dsl as l;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let mut errors = ValidationErrors::default();
+    validate_friend_list_name(name, &mut errors);
+    errors.into_result()?;
+
+    let mut conn = pool.get().await?;
+
+    let existing: i64 = l::friend_list
+        .filter(l::owner_id.eq(user_uuid))
+        .count()
+        .get_result(&mut conn)
+        .await?;
+
+    if existing >= MAX_FRIEND_LISTS {
+        return Err(AppError::conflict(
+            "friend_list_limit",
+            format!("you cannot have more than {} lists", MAX_FRIEND_LISTS),
+        ));
+    }
+
+    diesel::insert_into(l::friend_list)
+        .values(CreateFriendList {
+            owner_id: user_uuid,
+            name: name.to_string(),
+        })
+        .returning(FriendList::as_returning())
+        .get_result(&mut conn)
+        .await
+        .map_err(list_name_taken)
+}
+
+async fn rename_friend_list(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+    list_id: &str,
+    name: &str,
+) -> Result<FriendList, AppError> {
+    use shared::schema::friend_list::dsl as l;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let mut errors = ValidationErrors::default();
+    validate_friend_list_name(name, &mut errors);
+    errors.into_result()?;
+
+    let mut conn = pool.get().await?;
+
+    let list_uuid = find_owned_list(&mut conn, user_uuid, list_id).await?;
+
+    diesel::update(l::friend_list.filter(l::id.eq(list_uuid)))
+        .set(l::name.eq(name))
+        .returning(FriendList::as_returning())
+        .get_result(&mut conn)
+        .await
+        .map_err(list_name_taken)
+}
+
+async fn delete_friend_list(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+    list_id: &str,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::friend_list::dsl as l;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let mut conn = pool.get().await?;
+
+    let list_uuid = find_owned_list(&mut conn, user_uuid, list_id).await?;
+
+    // memberships go with the list through the foreign key
+    diesel::delete(l::friend_list.filter(l::id.eq(list_uuid)))
+        .execute(&mut conn)
+        .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"friend list deleted"}"#))
+}
+
+async fn add_list_member(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+    list_id: &str,
+    friend_id: &str,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::friend_list_member::dsl as lm;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let friend_uuid =
+        Uuid::parse_str(friend_id).map_err(|_| AppError::invalid_uuid("friend_id"))?;
+
+    let mut conn = pool.get().await?;
+
+    let list_uuid = find_owned_list(&mut conn, user_uuid, list_id).await?;
+
+    if !are_friends(&mut conn, user_uuid, friend_uuid).await? {
+        return Err(AppError::not_found(
+            "friend_not_found",
+            "not friends with this user",
+        ));
+    }
+
+    diesel::insert_into(lm::friend_list_member)
+        .values(CreateFriendListMember {
+            list_id: list_uuid,
+            friend_id: friend_uuid,
+        })
+        .on_conflict_do_nothing()
+        .execute(&mut conn)
+        .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"friend added to list"}"#))
+}
+
+async fn remove_list_member(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+    list_id: &str,
+    friend_id: &str,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::friend_list_member::dsl as lm;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let friend_uuid =
+        Uuid::parse_str(friend_id).map_err(|_| AppError::invalid_uuid("friend_id"))?;
+
+    let mut conn = pool.get().await?;
+
+    let list_uuid = find_owned_list(&mut conn, user_uuid, list_id).await?;
+
+    let removed = diesel::delete(
+        lm::friend_list_member
+            .filter(lm::list_id.eq(list_uuid))
+            .filter(lm::friend_id.eq(friend_uuid)),
+    )
+    .execute(&mut conn)
+    .await?;
+
+    if removed == 0 {
+        return Err(AppError::not_found(
+            "list_member_not_found",
+            "this friend is not in the list",
+        ));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"friend removed from list"}"#))
+}
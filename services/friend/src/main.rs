@@ -1,19 +1,29 @@
+mod block;
 mod friend;
+mod lists;
+mod meta;
+mod policy;
+mod presence;
 mod routes;
+mod suggestion;
 
+use crate::policy::spawn_friend_request_sweep;
+use crate::presence::spawn_redis_connect;
 use crate::routes::apply_routes;
 use shared::database::{PGPool, create_database_pool};
+use shared::error::json_error_handler;
+use shared::presence::PresenceRedis;
 
 use actix_cors::Cors;
 use actix_web::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, HeaderName};
 use actix_web::{App, HttpServer, web};
 use chrono::Utc;
-use std::io::{Error, ErrorKind, Result};
+use std::io::{Error, Result};
 
 const SERVER_URL: &str = "0.0.0.0";
 const HTTP_SERVER_PORT: u16 = 8081;
 
-pub async fn start_http_server(pool: PGPool) -> Result<()> {
+pub async fn start_http_server(pool: PGPool, redis: PresenceRedis) -> Result<()> {
     println!(
         "{:?}: Starting Actix web server on {:?}:{:?}",
         Utc::now().timestamp() as usize,
@@ -41,6 +51,8 @@ pub async fn start_http_server(pool: PGPool) -> Result<()> {
             )
             .configure(apply_routes)
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(redis.clone()))
+            .app_data(web::JsonConfig::default().error_handler(json_error_handler))
     })
     .bind((SERVER_URL, HTTP_SERVER_PORT))?
     .run()
@@ -54,7 +66,7 @@ async fn main() -> Result<()> {
     let pool = match result {
         Err(e) => {
             eprintln!("{}", e);
-            return Err(Error::new(ErrorKind::Other, e));
+            return Err(Error::other(e));
         }
         Ok(pool) => {
             println!(
@@ -65,5 +77,12 @@ async fn main() -> Result<()> {
         }
     };
 
-    start_http_server(pool).await
+    // presence needs redis, everything else starts without waiting for it
+    let redis = PresenceRedis::default();
+    spawn_redis_connect(redis.clone());
+
+    // drop expired requests and finished decline cooldowns in the background
+    spawn_friend_request_sweep(pool.clone());
+
+    start_http_server(pool, redis).await
 }
@@ -0,0 +1,92 @@
+use actix_web::{HttpRequest, HttpResponse, patch, web};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use uuid::Uuid;
+
+use shared::csrf::verify_csrf_token;
+use shared::database::PGPool;
+use shared::error::AppError;
+use shared::friendship::are_friends;
+use shared::jwt::{JwtTokenKind, extract_user_id};
+use shared::models::UpdateFriendMeta;
+use shared::validate::{ValidationErrors, validate_nickname};
+
+#[derive(Serialize, Queryable)]
+pub struct FriendMeta {
+    pub favourite: bool,
+    pub nickname: Option<String>,
+}
+
+#[patch("/meta/{friend_id}")]
+pub async fn patch_meta(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    req_body: web::Json<UpdateFriendMeta>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: PATCH /friend/meta from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let meta = update_friend_meta(pool, &user_id, path.trim(), req_body.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(meta))
+}
+
+/// Sets the favourite flag and nickname `user_id` keeps for `friend_id`. Both
+/// are private to `user_id` and only exist while the two are friends.
+async fn update_friend_meta(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+    friend_id: &str,
+    changes: UpdateFriendMeta,
+) -> Result<FriendMeta, AppError> {
+    use shared::schema::friend_meta::dsl as m;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let friend_uuid =
+        Uuid::parse_str(friend_id).map_err(|_| AppError::invalid_uuid("friend_id"))?;
+
+    let mut errors = ValidationErrors::default();
+    if let Some(Some(nickname)) = &changes.nickname {
+        validate_nickname(nickname, &mut errors);
+    }
+    errors.into_result()?;
+
+    let mut conn = pool.get().await?;
+
+    if !are_friends(&mut conn, user_uuid, friend_uuid).await? {
+        return Err(AppError::not_found(
+            "friend_not_found",
+            "not friends with this user",
+        ));
+    }
+
+    let meta = diesel::insert_into(m::friend_meta)
+        .values((
+            m::user_id.eq(user_uuid),
+            m::friend_id.eq(friend_uuid),
+            m::favourite.eq(changes.favourite.unwrap_or(false)),
+            m::nickname.eq(changes.nickname.clone().flatten()),
+        ))
+        .on_conflict((m::user_id, m::friend_id))
+        .do_update()
+        .set((&changes, m::updated_at.eq(Utc::now())))
+        .returning((m::favourite, m::nickname))
+        .get_result(&mut conn)
+        .await?;
+
+    Ok(meta)
+}
@@ -0,0 +1,68 @@
+use actix_web::rt;
+use chrono::{TimeDelta, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+
+use shared::database::PGPool;
+use shared::error::AppError;
+use shared::friend_request::FRIEND_REQUEST_POLICY;
+use shared::models::FriendRequestStatus;
+
+/// Deletes pending requests older than the expiry, declines whose cooldown is
+/// over and sends that no longer count towards the daily limit, returning how
+/// many requests went.
+pub async fn sweep_friend_requests(pool: &PGPool) -> Result<usize, AppError> {
+    use shared::schema::friend_request::dsl::*;
+    use shared::schema::friend_request_send::dsl as frs;
+
+    let mut conn = pool.get().await?;
+    let now = Utc::now();
+
+    let expired = diesel::delete(
+        friend_request
+            .filter(status.eq(FriendRequestStatus::Pending))
+            .filter(created_at.lt(now - FRIEND_REQUEST_POLICY.expiry)),
+    )
+    .execute(&mut conn)
+    .await?;
+
+    let cooled_down = diesel::delete(
+        friend_request
+            .filter(status.eq(FriendRequestStatus::Declined))
+            .filter(responded_at.lt(now - FRIEND_REQUEST_POLICY.decline_cooldown)),
+    )
+    .execute(&mut conn)
+    .await?;
+
+    diesel::delete(frs::friend_request_send.filter(frs::sent_at.lt(now - TimeDelta::days(1))))
+        .execute(&mut conn)
+        .await?;
+
+    Ok(expired + cooled_down)
+}
+
+/// Runs `sweep_friend_requests` every `sweep_interval` for as long as the
+/// server is up.
+pub fn spawn_friend_request_sweep(pool: PGPool) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(FRIEND_REQUEST_POLICY.sweep_interval);
+
+        loop {
+            interval.tick().await;
+
+            match sweep_friend_requests(&pool).await {
+                Ok(0) => {}
+                Ok(removed) => println!(
+                    "{:?}: Swept {} expired friend requests",
+                    Utc::now().timestamp() as usize,
+                    removed
+                ),
+                Err(e) => eprintln!(
+                    "{:?}: Friend request sweep failed: {}",
+                    Utc::now().timestamp() as usize,
+                    e
+                ),
+            }
+        }
+    });
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, delete, get, put, rt, web};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use shared::csrf::verify_csrf_token;
+use shared::database::{PGPool, create_redis_connection};
+use shared::error::AppError;
+use shared::friendship::friend_ids;
+use shared::jwt::{JwtTokenKind, extract_user_id};
+use shared::presence::{
+    Presence, PresenceRedis, PresenceState, clear_presence, load_presence, set_presence,
+};
+use shared::settings::load_settings_many;
+
+#[derive(Deserialize)]
+struct PresenceForm {
+    state: PresenceState,
+}
+
+#[derive(Serialize)]
+struct FriendPresence {
+    user_id: Uuid,
+    /// Absent while presence is unavailable.
+    presence: Option<Presence>,
+}
+
+#[put("/presence")]
+pub async fn put_presence(
+    redis: web::Data<PresenceRedis>,
+    req: HttpRequest,
+    req_body: web::Json<PresenceForm>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: PUT /friend/presence from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let user_uuid = Uuid::parse_str(&user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let mut redis = redis.get().ok_or_else(AppError::presence_unavailable)?;
+
+    set_presence(&mut redis, user_uuid, req_body.state).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"presence updated"}"#))
+}
+
+#[delete("/presence")]
+pub async fn delete_presence(
+    redis: web::Data<PresenceRedis>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: DELETE /friend/presence from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let user_uuid = Uuid::parse_str(&user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let mut redis = redis.get().ok_or_else(AppError::presence_unavailable)?;
+
+    clear_presence(&mut redis, user_uuid).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"presence cleared"}"#))
+}
+
+#[get("/presence")]
+pub async fn get_presence(
+    pool: web::Data<PGPool>,
+    redis: web::Data<PresenceRedis>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /friend/presence from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let presence = get_friends_presence(pool, &redis, &user_id).await?;
+
+    Ok(HttpResponse::Ok().json(presence))
+}
+
+/// How each of `friends` appears to someone they are friends with, given their
+/// own privacy settings. Presence is an extra on top of the friend list, so
+/// while redis is down or not yet connected this returns `None` instead of
+/// failing the request, logging any failure.
+pub async fn visible_presence(
+    conn: &mut AsyncPgConnection,
+    redis: &PresenceRedis,
+    friends: &[Uuid],
+) -> Result<Option<HashMap<Uuid, Presence>>, AppError> {
+    let Some(mut redis) = redis.get() else {
+        return Ok(None);
+    };
+
+    let mut stored = match load_presence(&mut redis, friends).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            eprintln!(
+                "{:?}: Failed to load presence: {:?}",
+                Utc::now().timestamp() as usize,
+                e
+            );
+            return Ok(None);
+        }
+    };
+
+    let mut settings = load_settings_many(conn, friends).await?;
+
+    Ok(Some(
+        friends
+            .iter()
+            .map(|id| {
+                let privacy = settings.remove(id).unwrap_or_default().privacy;
+
+                (
+                    *id,
+                    Presence::for_friend(stored.remove(id).unwrap_or_default(), &privacy),
+                )
+            })
+            .collect(),
+    ))
+}
+
+/// The presence of every friend of `user_id`.
+async fn get_friends_presence(
+    pool: web::Data<PGPool>,
+    redis: &PresenceRedis,
+    user_id: &str,
+) -> Result<Vec<FriendPresence>, AppError> {
+    use shared::schema::users::dsl as u;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let mut conn = pool.get().await?;
+
+    let friends = friend_ids(&mut conn, user_uuid).await?;
+
+    let friends: Vec<Uuid> = u::users
+        .filter(u::id.eq_any(&friends))
+        .filter(u::deleted_at.is_null())
+        .order(u::id.asc())
+        .select(u::id)
+        .load(&mut conn)
+        .await?;
+
+    let mut presence = visible_presence(&mut conn, redis, &friends).await?;
+
+    Ok(friends
+        .into_iter()
+        .map(|id| FriendPresence {
+            user_id: id,
+            presence: presence.as_mut().and_then(|presence| presence.remove(&id)),
+        })
+        .collect())
+}
+
+/// How long to wait between attempts to reach redis while it is down at boot.
+const REDIS_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Connects `redis` in the background, retrying until redis is reachable, so
+/// the server can start and serve everything but presence in the meantime.
+pub fn spawn_redis_connect(redis: PresenceRedis) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(REDIS_RETRY_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match create_redis_connection().await {
+                Ok(conn) => {
+                    redis.set(conn);
+                    println!(
+                        "{:?}: Redis connection created",
+                        Utc::now().timestamp() as usize
+                    );
+                    return;
+                }
+                Err(e) => eprintln!(
+                    "{:?}: Failed to connect to redis, presence is unavailable: {}",
+                    Utc::now().timestamp() as usize,
+                    e
+                ),
+            }
+        }
+    });
+}
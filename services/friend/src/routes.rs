@@ -1,14 +1,44 @@
-use crate::friend::{get_all, get_friend_requests, patch_add, post_add, post_remove};
+use crate::block::{delete_block, get_blocked, post_block};
+use crate::friend::{
+    delete_friend_request, get_all, get_count, get_friend_requests, patch_add, post_add,
+    post_remove,
+};
+use crate::lists::{
+    delete_list, delete_list_member, get_lists, patch_list, post_list, put_list_member,
+};
+use crate::meta::patch_meta;
+use crate::presence::{delete_presence, get_presence, put_presence};
+use crate::suggestion::{get_mutual, get_suggestions, post_dismiss_suggestion};
 use actix_web::web;
 
-static SCOPE_HANDLERS: &[(&str, fn(&mut web::ServiceConfig))] = &[("friend", routes)];
+type ScopeHandler = (&'static str, fn(&mut web::ServiceConfig));
+
+static SCOPE_HANDLERS: &[ScopeHandler] = &[("friend", routes)];
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg.service(get_all)
+        .service(get_count)
         .service(get_friend_requests)
+        .service(delete_friend_request)
         .service(patch_add)
         .service(post_add)
-        .service(post_remove);
+        .service(post_remove)
+        .service(post_block)
+        .service(delete_block)
+        .service(get_blocked)
+        .service(get_mutual)
+        .service(get_suggestions)
+        .service(post_dismiss_suggestion)
+        .service(patch_meta)
+        .service(get_lists)
+        .service(post_list)
+        .service(patch_list)
+        .service(delete_list)
+        .service(put_list_member)
+        .service(delete_list_member)
+        .service(get_presence)
+        .service(put_presence)
+        .service(delete_presence);
 }
 
 pub fn apply_routes(cfg: &mut web::ServiceConfig) {
@@ -0,0 +1,310 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, get, post, web};
+use chrono::Utc;
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use diesel::sql_types::{Array, BigInt, Timestamptz};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use shared::block::is_blocked_between;
+use shared::csrf::verify_csrf_token;
+use shared::database::PGPool;
+use shared::error::AppError;
+use shared::friend_request::FRIEND_REQUEST_POLICY;
+use shared::friendship::friend_ids;
+use shared::jwt::{JwtTokenKind, extract_user_id};
+use shared::models::{CreateDismissedSuggestion, PublicUserRow};
+use shared::profile::PublicUser;
+use shared::validate::ValidationErrors;
+
+pub const SUGGESTIONS_DEFAULT_LIMIT: u32 = 20;
+pub const SUGGESTIONS_MAX_LIMIT: u32 = 50;
+
+#[derive(Deserialize)]
+struct SuggestionsQuery {
+    limit: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct DismissForm {
+    user_id: String,
+}
+
+#[derive(Serialize)]
+struct Suggestion {
+    #[serde(flatten)]
+    user: PublicUser,
+    mutual_friends: i64,
+}
+
+#[get("/mutual/{user_id}")]
+pub async fn get_mutual(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /friend/mutual from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let mutual_friends = get_mutual_friends(pool, &user_id, path.trim()).await?;
+
+    Ok(HttpResponse::Ok().json(mutual_friends))
+}
+
+#[get("/suggestions")]
+pub async fn get_suggestions(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    query: web::Query<SuggestionsQuery>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /friend/suggestions from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let limit = query.limit.unwrap_or(SUGGESTIONS_DEFAULT_LIMIT);
+
+    let mut errors = ValidationErrors::default();
+    if limit == 0 || limit > SUGGESTIONS_MAX_LIMIT {
+        errors.add_length("limit", 1, SUGGESTIONS_MAX_LIMIT as usize);
+    }
+    errors.into_result()?;
+
+    let suggestions = suggest_friends(pool, &user_id, limit).await?;
+
+    Ok(HttpResponse::Ok().json(suggestions))
+}
+
+#[post("/suggestions/dismiss")]
+pub async fn post_dismiss_suggestion(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    req_body: web::Json<DismissForm>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: POST /friend/suggestions/dismiss from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    dismiss_suggestion(pool, &user_id, req_body.user_id.trim()).await
+}
+
+/// Friends `user_id` has in common with `other_id`, by username.
+async fn get_mutual_friends(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+    other_id: &str,
+) -> Result<Vec<PublicUser>, AppError> {
+    use shared::schema::users::dsl as u;
+
+    let mut conn = pool.get().await?;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let other_uuid =
+        Uuid::parse_str(other_id).map_err(|_| AppError::invalid_uuid("other_user_id"))?;
+
+    if other_uuid == user_uuid {
+        return Err(AppError::bad_request(
+            "cannot_compare_self",
+            "mutual friends need another user",
+        ));
+    }
+
+    let exists = diesel::select(exists(
+        u::users
+            .filter(u::id.eq(other_uuid))
+            .filter(u::deleted_at.is_null()),
+    ))
+    .get_result::<bool>(&mut conn)
+    .await?;
+
+    if !exists || is_blocked_between(&mut conn, user_uuid, other_uuid).await? {
+        return Err(AppError::not_found("user_not_found", "user not found"));
+    }
+
+    let friends_of_user: HashSet<Uuid> = friend_ids(&mut conn, user_uuid)
+        .await?
+        .into_iter()
+        .collect();
+
+    let mutual: Vec<Uuid> = friend_ids(&mut conn, other_uuid)
+        .await?
+        .into_iter()
+        .filter(|id| friends_of_user.contains(id))
+        .collect();
+
+    if mutual.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let users: Vec<PublicUserRow> = u::users
+        .filter(u::id.eq_any(&mutual))
+        .filter(u::deleted_at.is_null())
+        .order(u::username_normalised.asc())
+        .select(PublicUserRow::as_select())
+        .load(&mut conn)
+        .await?;
+
+    Ok(users.into_iter().map(PublicUser::from).collect())
+}
+
+/// Counts the friends `$1` shares with each suggestion and keeps the top `$4`.
+/// `$2` holds the viewer's friends, and friend rows are stored once per pair,
+/// so the second hop looks them up on both sides. Pending requests older than
+/// `$3` have expired and no longer hide a suggestion.
+const SUGGESTIONS_QUERY: &str = "
+    WITH hops AS (
+        SELECT user2 AS candidate FROM friend WHERE user1 = ANY($2)
+        UNION ALL
+        SELECT user1 FROM friend WHERE user2 = ANY($2)
+    )
+    SELECT h.candidate, count(*) AS mutual_friends
+    FROM hops h
+    JOIN users u ON u.id = h.candidate
+    WHERE h.candidate <> $1
+        AND h.candidate <> ALL($2)
+        AND u.deleted_at IS NULL
+        AND NOT EXISTS (
+            SELECT 1 FROM friend_request fr
+            WHERE fr.status = 'pending'
+                AND fr.created_at >= $3
+                AND ((fr.requester = $1 AND fr.receiver = h.candidate)
+                    OR (fr.requester = h.candidate AND fr.receiver = $1))
+        )
+        AND NOT EXISTS (
+            SELECT 1 FROM user_block b
+            WHERE (b.blocker = $1 AND b.blocked = h.candidate)
+                OR (b.blocker = h.candidate AND b.blocked = $1)
+        )
+        AND NOT EXISTS (
+            SELECT 1 FROM dismissed_suggestion d
+            WHERE d.user_id = $1 AND d.dismissed = h.candidate
+        )
+    GROUP BY h.candidate
+    ORDER BY mutual_friends DESC, h.candidate
+    LIMIT $4
+";
+
+#[derive(QueryableByName)]
+struct MutualCount {
+    #[diesel(sql_type = diesel::sql_types::Uuid)]
+    candidate: Uuid,
+    #[diesel(sql_type = BigInt)]
+    mutual_friends: i64,
+}
+
+/// Friends of `viewer`'s friends ranked by how many friends they share, most
+/// first. Existing friends, users with a pending request either way, blocked
+/// users and dismissed suggestions are left out.
+async fn suggest_friends(
+    pool: web::Data<PGPool>,
+    viewer: &str,
+    limit: u32,
+) -> Result<Vec<Suggestion>, AppError> {
+    use shared::schema::users::dsl as u;
+
+    let mut conn = pool.get().await?;
+
+    let viewer_uuid = Uuid::parse_str(viewer).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let friends = friend_ids(&mut conn, viewer_uuid).await?;
+
+    if friends.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let expired_before = Utc::now() - FRIEND_REQUEST_POLICY.expiry;
+
+    let ranked: Vec<MutualCount> = diesel::sql_query(SUGGESTIONS_QUERY)
+        .bind::<diesel::sql_types::Uuid, _>(viewer_uuid)
+        .bind::<Array<diesel::sql_types::Uuid>, _>(&friends)
+        .bind::<Timestamptz, _>(expired_before)
+        .bind::<BigInt, _>(i64::from(limit))
+        .load(&mut conn)
+        .await?;
+
+    let ids: Vec<Uuid> = ranked.iter().map(|count| count.candidate).collect();
+
+    let mut users: HashMap<Uuid, PublicUserRow> = u::users
+        .filter(u::id.eq_any(&ids))
+        .filter(u::deleted_at.is_null())
+        .select(PublicUserRow::as_select())
+        .load::<PublicUserRow>(&mut conn)
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect();
+
+    Ok(ranked
+        .into_iter()
+        .filter_map(|count| {
+            users.remove(&count.candidate).map(|user| Suggestion {
+                user: user.into(),
+                mutual_friends: count.mutual_friends,
+            })
+        })
+        .collect())
+}
+
+/// Stops `dismissed_id` from being suggested to `user_id` again.
+async fn dismiss_suggestion(
+    pool: web::Data<PGPool>,
+    user_id: &str,
+    dismissed_id: &str,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::dismissed_suggestion::dsl as d;
+
+    let mut conn = pool.get().await?;
+
+    let user_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let dismissed_uuid =
+        Uuid::parse_str(dismissed_id).map_err(|_| AppError::invalid_uuid("dismissed_user_id"))?;
+
+    if dismissed_uuid == user_uuid {
+        return Err(AppError::bad_request(
+            "cannot_dismiss_self",
+            "you cannot dismiss yourself",
+        ));
+    }
+
+    diesel::insert_into(d::dismissed_suggestion)
+        .values(CreateDismissedSuggestion {
+            user_id: user_uuid,
+            dismissed: dismissed_uuid,
+        })
+        .on_conflict_do_nothing()
+        .execute(&mut conn)
+        .await
+        .map_err(|e| match AppError::from(e) {
+            AppError::NotFound { .. } => AppError::not_found("user_not_found", "user not found"),
+            e => e,
+        })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"suggestion dismissed"}"#))
+}
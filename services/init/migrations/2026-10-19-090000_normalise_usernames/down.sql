@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX idx_users_username_skeleton;
+DROP INDEX idx_users_username_normalised;
+
+ALTER TABLE users DROP COLUMN username_skeleton;
+ALTER TABLE users DROP COLUMN username_normalised;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- Case-folded NFKC form of the username, used for uniqueness and lookups, and
+-- the Unicode confusable skeleton, used to reject look-alike usernames. SQL
+-- has no full Unicode case folding, so existing rows start with their plain
+-- username and a NULL skeleton, and the auth service folds both on startup.
+ALTER TABLE users ADD COLUMN username_normalised TEXT;
+ALTER TABLE users ADD COLUMN username_skeleton TEXT;
+
+UPDATE users SET username_normalised = username;
+
+ALTER TABLE users ALTER COLUMN username_normalised SET NOT NULL;
+
+CREATE UNIQUE INDEX idx_users_username_normalised ON users (username_normalised);
+CREATE INDEX idx_users_username_skeleton ON users (username_skeleton);
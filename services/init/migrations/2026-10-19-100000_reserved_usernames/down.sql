@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE reserved_username_grant;
+
+ALTER TABLE users DROP COLUMN is_admin;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE reserved_username_grant (
+    username_normalised TEXT PRIMARY KEY, -- Case-folded NFKC form of the reserved name
+    user_id UUID NOT NULL,
+    granted_by UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_reserved_username_grant_user FOREIGN KEY (user_id) REFERENCES users(id),
+    CONSTRAINT fk_reserved_username_grant_granted_by FOREIGN KEY (granted_by) REFERENCES users(id)
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+-- The original formatting of normalised phone numbers is not kept, so only the
+-- flag is undone.
+ALTER TABLE users DROP COLUMN phone_number_invalid;
@@ -0,0 +1,37 @@
+-- Your SQL goes here
+-- Phone numbers are stored in E.164 form. Numbers dialled with the "00"
+-- international prefix are converted here, numbers in national format need
+-- country metadata and are converted by the auth service on startup, which
+-- flags the ones it cannot convert so they are only tried once.
+ALTER TABLE users ADD COLUMN phone_number_invalid BOOLEAN NOT NULL DEFAULT false;
+
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(normalised, ', ') INTO duplicates
+    FROM (
+        SELECT regexp_replace(phone_number, '^00', '+') AS normalised
+        FROM users
+        GROUP BY normalised
+        HAVING count(*) > 1
+    ) AS d;
+
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'phone numbers collide after normalisation, resolve them first: %', duplicates;
+    END IF;
+END $$;
+
+UPDATE users SET phone_number = regexp_replace(phone_number, '^00', '+')
+WHERE phone_number LIKE '00%';
+
+DO $$
+DECLARE
+    national INTEGER;
+BEGIN
+    SELECT count(*) INTO national FROM users WHERE phone_number NOT LIKE '+%';
+
+    IF national > 0 THEN
+        RAISE NOTICE '% phone numbers are in national format and will be normalised by the auth service', national;
+    END IF;
+END $$;
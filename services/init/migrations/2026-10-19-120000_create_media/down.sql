@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users DROP COLUMN profile_pic_id;
+ALTER TABLE users RENAME COLUMN legacy_profile_pic TO profile_pic;
+
+DROP TABLE media;
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+CREATE TABLE media (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    owner_id UUID NOT NULL,
+    content_hash TEXT NOT NULL, -- SHA-256 of the stored bytes, also the storage key
+    content_type TEXT NOT NULL,
+    byte_size BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_media_owner FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT uq_media_owner_content UNIQUE (owner_id, content_hash)
+);
+
+CREATE INDEX idx_media_content_hash ON media (content_hash);
+
+-- Base64 data URLs are moved into media storage by the profile service on startup
+ALTER TABLE users RENAME COLUMN profile_pic TO legacy_profile_pic;
+
+ALTER TABLE users ADD COLUMN profile_pic_id UUID;
+ALTER TABLE users ADD CONSTRAINT fk_users_profile_pic
+    FOREIGN KEY (profile_pic_id) REFERENCES media(id) ON DELETE SET NULL;
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX idx_media_variant_of;
+
+ALTER TABLE media DROP COLUMN variant_of;
+ALTER TABLE media DROP COLUMN height;
+ALTER TABLE media DROP COLUMN width;
+ALTER TABLE media DROP COLUMN kind;
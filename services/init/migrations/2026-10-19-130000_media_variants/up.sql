@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE media ADD COLUMN kind TEXT NOT NULL DEFAULT 'upload'; -- 'upload' / 'avatar'
+ALTER TABLE media ADD COLUMN width INTEGER;
+ALTER TABLE media ADD COLUMN height INTEGER;
+ALTER TABLE media ADD COLUMN variant_of UUID; -- Original this is a resized variant of
+ALTER TABLE media ADD CONSTRAINT fk_media_variant_of
+    FOREIGN KEY (variant_of) REFERENCES media(id) ON DELETE CASCADE;
+
+CREATE INDEX idx_media_variant_of ON media (variant_of);
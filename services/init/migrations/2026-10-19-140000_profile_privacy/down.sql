@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users DROP COLUMN phone_number_visibility;
+ALTER TABLE users DROP COLUMN email_visibility;
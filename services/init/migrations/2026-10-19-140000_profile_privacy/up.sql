@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Who may see the email address and phone number on a user's public profile
+ALTER TABLE users ADD COLUMN email_visibility TEXT NOT NULL DEFAULT 'nobody'
+    CHECK (email_visibility IN ('everyone', 'friends', 'nobody'));
+ALTER TABLE users ADD COLUMN phone_number_visibility TEXT NOT NULL DEFAULT 'nobody'
+    CHECK (phone_number_visibility IN ('everyone', 'friends', 'nobody'));
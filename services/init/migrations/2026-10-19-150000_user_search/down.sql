@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX idx_users_email_lower;
+DROP INDEX idx_users_username_normalised_trgm;
+DROP INDEX idx_users_username_normalised_prefix;
+
+ALTER TABLE users DROP COLUMN deleted_at;
+ALTER TABLE users DROP COLUMN phone_number_verified;
+ALTER TABLE users DROP COLUMN email_verified;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Only verified contact details can be used to discover a user
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE users ADD COLUMN phone_number_verified BOOLEAN NOT NULL DEFAULT false;
+
+-- Deleted accounts are kept until purged but hidden from other users
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- text_pattern_ops serves prefix (LIKE 'abc%') lookups, the trigram index fuzzy ones
+CREATE INDEX idx_users_username_normalised_prefix ON users (username_normalised text_pattern_ops);
+CREATE INDEX idx_users_username_normalised_trgm ON users USING GIN (username_normalised gin_trgm_ops);
+CREATE INDEX idx_users_email_lower ON users (lower(email));
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX idx_users_display_name_trgm;
+
+ALTER TABLE users DROP COLUMN timezone;
+ALTER TABLE users DROP COLUMN status_expires_at;
+ALTER TABLE users DROP COLUMN status_emoji;
+ALTER TABLE users DROP COLUMN status_text;
+ALTER TABLE users DROP COLUMN pronouns;
+ALTER TABLE users DROP COLUMN display_name;
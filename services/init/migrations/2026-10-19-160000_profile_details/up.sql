@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN display_name TEXT;
+ALTER TABLE users ADD COLUMN pronouns TEXT;
+ALTER TABLE users ADD COLUMN status_text TEXT;
+ALTER TABLE users ADD COLUMN status_emoji TEXT;
+ALTER TABLE users ADD COLUMN status_expires_at TIMESTAMPTZ; -- NULL keeps the status until it is cleared
+ALTER TABLE users ADD COLUMN timezone TEXT; -- IANA time zone name, e.g. Europe/London
+
+CREATE INDEX idx_users_display_name_trgm ON users USING GIN (lower(display_name) gin_trgm_ops);
@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users ADD COLUMN email_visibility TEXT NOT NULL DEFAULT 'nobody'
+    CHECK (email_visibility IN ('everyone', 'friends', 'nobody'));
+ALTER TABLE users ADD COLUMN phone_number_visibility TEXT NOT NULL DEFAULT 'nobody'
+    CHECK (phone_number_visibility IN ('everyone', 'friends', 'nobody'));
+
+UPDATE users SET
+    email_visibility = COALESCE(s.settings #>> '{privacy,email_visibility}', 'nobody'),
+    phone_number_visibility = COALESCE(s.settings #>> '{privacy,phone_number_visibility}', 'nobody')
+FROM user_settings s
+WHERE s.user_id = users.id;
+
+DROP TABLE user_settings;
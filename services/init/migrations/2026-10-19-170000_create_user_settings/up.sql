@@ -0,0 +1,20 @@
+-- Your SQL goes here
+CREATE TABLE user_settings (
+    user_id UUID PRIMARY KEY,
+    version INTEGER NOT NULL, -- Schema version of the settings document
+    settings JSONB NOT NULL DEFAULT '{}', -- Missing keys take their default value
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_user_settings_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Profile privacy now lives in the settings document
+INSERT INTO user_settings (user_id, version, settings)
+SELECT id, 1, jsonb_build_object('privacy', jsonb_build_object(
+    'email_visibility', email_visibility,
+    'phone_number_visibility', phone_number_visibility
+))
+FROM users
+WHERE email_visibility <> 'nobody' OR phone_number_visibility <> 'nobody';
+
+ALTER TABLE users DROP COLUMN email_visibility;
+ALTER TABLE users DROP COLUMN phone_number_visibility;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- Former usernames, kept so renamed users can still be found by their old name
+-- and so the old name is held back from other users for a while
+CREATE TABLE username_history (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    username TEXT NOT NULL,
+    username_normalised TEXT NOT NULL, -- Case-folded NFKC form of the former username
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_username_history_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_username_history_username_normalised ON username_history (username_normalised, changed_at);
+CREATE INDEX idx_username_history_user_id ON username_history (user_id, changed_at);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER set_updated_at ON users;
+
+ALTER TABLE users DROP COLUMN updated_at;
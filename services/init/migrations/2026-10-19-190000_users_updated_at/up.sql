@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- Bumped by a trigger on every change, profile ETags are derived from it
+ALTER TABLE users ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+SELECT diesel_manage_updated_at('users');
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE user_block (
+    blocker UUID NOT NULL,
+    blocked UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (blocker, blocked),
+    CONSTRAINT fk_user_block_blocker FOREIGN KEY (blocker) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT fk_user_block_blocked FOREIGN KEY (blocked) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_user_block_blocked ON user_block (blocked);
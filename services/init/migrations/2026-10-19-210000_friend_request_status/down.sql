@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS idx_friend_request_receiver_pending;
+
+DELETE FROM friend_request WHERE status <> 'pending';
+
+ALTER TABLE friend_request DROP COLUMN IF EXISTS responded_at;
+ALTER TABLE friend_request DROP COLUMN IF EXISTS status;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE friend_request ADD COLUMN status TEXT NOT NULL DEFAULT 'pending'
+    CHECK (status IN ('pending', 'accepted', 'declined'));
+ALTER TABLE friend_request ADD COLUMN responded_at TIMESTAMPTZ;
+
+CREATE INDEX idx_friend_request_receiver_pending ON friend_request (receiver) WHERE status = 'pending';
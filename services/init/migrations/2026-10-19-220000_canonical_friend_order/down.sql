@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE friend DROP CONSTRAINT IF EXISTS friend_canonical_order;
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+DELETE FROM friend WHERE user1 = user2;
+
+-- keep one row per pair, dated from whichever accept came first
+UPDATE friend kept SET created_at = dup.created_at
+FROM friend dup
+WHERE kept.user1 < kept.user2
+    AND dup.user1 = kept.user2
+    AND dup.user2 = kept.user1
+    AND dup.created_at < kept.created_at;
+
+DELETE FROM friend dup
+USING friend kept
+WHERE dup.user1 > dup.user2
+    AND kept.user1 = dup.user2
+    AND kept.user2 = dup.user1;
+
+UPDATE friend SET user1 = user2, user2 = user1 WHERE user1 > user2;
+
+ALTER TABLE friend ADD CONSTRAINT friend_canonical_order CHECK (user1 < user2);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE dismissed_suggestion (
+    user_id UUID NOT NULL,
+    dismissed UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, dismissed),
+    CONSTRAINT fk_dismissed_suggestion_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT fk_dismissed_suggestion_dismissed FOREIGN KEY (dismissed) REFERENCES users(id) ON DELETE CASCADE
+);
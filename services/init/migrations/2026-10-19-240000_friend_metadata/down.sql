@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE friend_list_member;
+DROP TABLE friend_list;
+DROP TABLE friend_meta;
@@ -0,0 +1,32 @@
+-- Your SQL goes here
+CREATE TABLE friend_meta (
+    user_id UUID NOT NULL,
+    friend_id UUID NOT NULL,
+    favourite BOOLEAN NOT NULL DEFAULT FALSE,
+    nickname TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, friend_id),
+    CONSTRAINT fk_friend_meta_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT fk_friend_meta_friend FOREIGN KEY (friend_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE TABLE friend_list (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    owner_id UUID NOT NULL,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_friend_list_owner FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX idx_friend_list_owner_name ON friend_list (owner_id, lower(name));
+
+CREATE TABLE friend_list_member (
+    list_id UUID NOT NULL,
+    friend_id UUID NOT NULL,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (list_id, friend_id),
+    CONSTRAINT fk_friend_list_member_list FOREIGN KEY (list_id) REFERENCES friend_list(id) ON DELETE CASCADE,
+    CONSTRAINT fk_friend_list_member_friend FOREIGN KEY (friend_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_friend_list_member_friend ON friend_list_member (friend_id);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE friend_request_send (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    requester UUID NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_friend_request_send_requester FOREIGN KEY (requester) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_friend_request_send_requester ON friend_request_send (requester, sent_at);
+
+-- requests sent in the current window still count towards the daily limit
+INSERT INTO friend_request_send (requester, sent_at)
+SELECT requester, created_at FROM friend_request WHERE created_at > NOW() - INTERVAL '1 day';
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- The profile service moves legacy profile pictures to media storage on
+-- startup and flags the ones that are not a usable image so they are only
+-- tried once.
+ALTER TABLE users ADD COLUMN legacy_profile_pic_invalid BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,172 @@
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, delete, get, post, web};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use shared::csrf::verify_csrf_token;
+use shared::database::PGPool;
+use shared::error::AppError;
+use shared::jwt::{JwtTokenKind, extract_user_id};
+use shared::models::{CreateReservedUsernameGrant, ReservedUsernameGrant};
+use shared::username::{USERNAME_LISTS, fold_username, normalise_username};
+
+#[derive(Deserialize)]
+struct GrantReservedUsernameForm {
+    username: String,
+    user_id: String,
+}
+
+#[get("/admin/reserved-usernames")]
+pub async fn get_reserved_username_grants(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::reserved_username_grant::dsl::*;
+
+    println!(
+        "{:?}: GET /profile/admin/reserved-usernames from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    require_admin(&pool, &req).await?;
+
+    let mut conn = pool.get().await?;
+
+    let grants: Vec<ReservedUsernameGrant> = reserved_username_grant
+        .order(created_at.desc())
+        .load(&mut conn)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(grants))
+}
+
+#[post("/admin/reserved-usernames")]
+pub async fn post_reserved_username_grant(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    req_body: web::Json<GrantReservedUsernameForm>,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::reserved_username_grant::dsl::*;
+
+    println!(
+        "{:?}: POST /profile/admin/reserved-usernames from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    let admin_uuid = require_admin(&pool, &req).await?;
+
+    let reserved_name = normalise_username(&req_body.username);
+
+    if !USERNAME_LISTS.is_reserved(&reserved_name) {
+        return Err(AppError::bad_request(
+            "username_not_reserved",
+            "username is not reserved",
+        ));
+    }
+
+    let grantee_uuid =
+        Uuid::parse_str(req_body.user_id.trim()).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let grant = CreateReservedUsernameGrant {
+        username_normalised: fold_username(&reserved_name),
+        user_id: grantee_uuid,
+        granted_by: admin_uuid,
+    };
+
+    let mut conn = pool.get().await?;
+
+    // re-granting a name moves it to the new user
+    diesel::insert_into(reserved_username_grant)
+        .values(&grant)
+        .on_conflict(username_normalised)
+        .do_update()
+        .set((
+            user_id.eq(grantee_uuid),
+            granted_by.eq(admin_uuid),
+            created_at.eq(Utc::now()),
+        ))
+        .execute(&mut conn)
+        .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"reserved username granted"}"#))
+}
+
+#[delete("/admin/reserved-usernames/{username}")]
+pub async fn delete_reserved_username_grant(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::reserved_username_grant::dsl::*;
+
+    println!(
+        "{:?}: DELETE /profile/admin/reserved-usernames from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    require_admin(&pool, &req).await?;
+
+    let mut conn = pool.get().await?;
+
+    let rows_deleted = diesel::delete(
+        reserved_username_grant.filter(username_normalised.eq(fold_username(&path))),
+    )
+    .execute(&mut conn)
+    .await?;
+
+    if rows_deleted == 0 {
+        return Err(AppError::not_found(
+            "grant_not_found",
+            "reserved username grant not found",
+        ));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"reserved username grant revoked"}"#))
+}
+
+/// Returns the caller's id when their access token belongs to an admin.
+async fn require_admin(pool: &PGPool, req: &HttpRequest) -> Result<Uuid, AppError> {
+    use shared::schema::users::dsl::*;
+
+    let user_id = extract_user_id(req, JwtTokenKind::ACCESS)?;
+
+    let user_uuid = Uuid::parse_str(&user_id)
+        .map_err(|_| AppError::unauthorized("invalid_token", "invalid access token"))?;
+
+    let mut conn = pool.get().await?;
+
+    let admin = users
+        .filter(id.eq(user_uuid))
+        .select(is_admin)
+        .first::<bool>(&mut conn)
+        .await
+        .optional()?
+        .unwrap_or(false);
+
+    if !admin {
+        return Err(AppError::forbidden(
+            "admin_required",
+            "admin access required",
+        ));
+    }
+
+    Ok(user_uuid)
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use actix_multipart::Multipart;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, delete, put, web};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use uuid::Uuid;
+
+use crate::media::read_file_field;
+use shared::avatar::{AVATAR_SIZES, process_avatar, store_avatar};
+use shared::csrf::verify_csrf_token;
+use shared::database::PGPool;
+use shared::error::AppError;
+use shared::jwt::{JwtTokenKind, extract_user_id};
+use shared::media::{MediaStore, delete_unreferenced_media, media_url, media_variant_url};
+use shared::validate::ValidationErrors;
+
+#[put("/self/avatar")]
+pub async fn put_avatar(
+    pool: web::Data<PGPool>,
+    store: web::Data<dyn MediaStore>,
+    req: HttpRequest,
+    mut payload: Multipart,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::users::dsl::*;
+
+    println!(
+        "{:?}: PUT /profile/self/avatar from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let user_uuid = Uuid::parse_str(&user_id)
+        .map_err(|_| AppError::unauthorized("invalid_token", "invalid access token"))?;
+
+    let mut errors = ValidationErrors::default();
+
+    let Some(bytes) = read_file_field(&mut payload, &mut errors).await? else {
+        if errors.is_empty() {
+            errors.add("file", "required");
+        }
+        return Err(AppError::Validation(errors));
+    };
+
+    // decoding and resizing is CPU bound, keep it off the async workers
+    let (variants, errors) = web::block(move || {
+        let mut errors = errors;
+        let variants = process_avatar(&bytes, &mut errors);
+        (variants, errors)
+    })
+    .await
+    .map_err(|_| AppError::internal("avatar_failed", "internal server error"))?;
+
+    errors.into_result()?;
+
+    let avatar = store_avatar(&pool, &**store, user_uuid, variants.unwrap_or_default()).await?;
+
+    let mut conn = pool.get().await?;
+
+    let previous = users
+        .filter(id.eq(user_uuid))
+        .select(profile_pic_id)
+        .first::<Option<Uuid>>(&mut conn)
+        .await
+        .optional()?
+        .flatten();
+
+    diesel::update(users.filter(id.eq(user_uuid)))
+        .set(profile_pic_id.eq(avatar.id))
+        .execute(&mut conn)
+        .await?;
+
+    if previous != Some(avatar.id) {
+        remove_previous_avatar(&pool, &**store, previous).await;
+    }
+
+    let variant_urls: BTreeMap<String, String> = AVATAR_SIZES
+        .iter()
+        .map(|size| (size.to_string(), media_variant_url(avatar.id, *size)))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": avatar.id,
+        "url": media_url(avatar.id),
+        "variants": variant_urls,
+    })))
+}
+
+#[delete("/self/avatar")]
+pub async fn delete_avatar(
+    pool: web::Data<PGPool>,
+    store: web::Data<dyn MediaStore>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    use shared::schema::users::dsl::*;
+
+    println!(
+        "{:?}: DELETE /profile/self/avatar from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let user_uuid = Uuid::parse_str(&user_id)
+        .map_err(|_| AppError::unauthorized("invalid_token", "invalid access token"))?;
+
+    let mut conn = pool.get().await?;
+
+    let previous = users
+        .filter(id.eq(user_uuid))
+        .select(profile_pic_id)
+        .first::<Option<Uuid>>(&mut conn)
+        .await
+        .optional()?
+        .flatten();
+
+    diesel::update(users.filter(id.eq(user_uuid)))
+        .set(profile_pic_id.eq(None::<Uuid>))
+        .execute(&mut conn)
+        .await?;
+
+    remove_previous_avatar(&pool, &**store, previous).await;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(r#"{"detail":"avatar removed"}"#))
+}
+
+/// Deletes the avatar a user just moved away from unless someone still uses
+/// it. The profile has already changed by then, so failures are only logged.
+async fn remove_previous_avatar(pool: &PGPool, store: &dyn MediaStore, previous: Option<Uuid>) {
+    let Some(previous) = previous else {
+        return;
+    };
+
+    if let Err(e) = delete_unreferenced_media(pool, store, previous).await {
+        eprintln!(
+            "{:?}: Failed to delete avatar {}: {:?}",
+            Utc::now().timestamp() as usize,
+            previous,
+            e
+        );
+    }
+}
@@ -1,19 +1,28 @@
+mod admin;
+mod avatar;
+mod media;
 mod profile;
 mod routes;
+mod search;
+mod settings;
 
 use crate::routes::apply_routes;
+use shared::avatar::backfill_legacy_profile_pics;
 use shared::database::{PGPool, create_database_pool};
+use shared::error::json_error_handler;
+use shared::media::{MediaStore, media_store_from_env};
 
 use actix_cors::Cors;
 use actix_web::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, HeaderName};
 use actix_web::{App, HttpServer, web};
 use chrono::Utc;
-use std::io::{Error, ErrorKind, Result};
+use std::io::{Error, Result};
+use std::sync::Arc;
 
 const SERVER_URL: &str = "0.0.0.0";
 const HTTP_SERVER_PORT: u16 = 8082;
 
-pub async fn start_http_server(pool: PGPool) -> Result<()> {
+pub async fn start_http_server(pool: PGPool, store: Arc<dyn MediaStore>) -> Result<()> {
     println!(
         "{:?}: Starting Actix web server on {:?}:{:?}",
         Utc::now().timestamp() as usize,
@@ -41,6 +50,8 @@ pub async fn start_http_server(pool: PGPool) -> Result<()> {
             )
             .configure(apply_routes)
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(store.clone()))
+            .app_data(web::JsonConfig::default().error_handler(json_error_handler))
     })
     .bind((SERVER_URL, HTTP_SERVER_PORT))?
     .run()
@@ -54,7 +65,7 @@ async fn main() -> Result<()> {
     let pool = match result {
         Err(e) => {
             eprintln!("{}", e);
-            return Err(Error::new(ErrorKind::Other, e));
+            return Err(Error::other(e));
         }
         Ok(pool) => {
             println!(
@@ -65,5 +76,24 @@ async fn main() -> Result<()> {
         }
     };
 
-    start_http_server(pool).await
+    let store = match media_store_from_env() {
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(e);
+        }
+        Ok(store) => store,
+    };
+
+    // move base64 profile pictures from before media storage existed
+    match backfill_legacy_profile_pics(&pool, &*store).await {
+        Ok(0) => {}
+        Ok(count) => println!(
+            "{:?}: Moved {:?} profile pictures to media storage",
+            Utc::now().timestamp() as usize,
+            count
+        ),
+        Err(e) => eprintln!("{}", e),
+    }
+
+    start_http_server(pool, store).await
 }
@@ -0,0 +1,102 @@
+use actix_multipart::Multipart;
+use actix_web::http::header::{CacheControl, CacheDirective, ContentType, ETag, EntityTag};
+use actix_web::{HttpRequest, HttpResponse, get, web};
+use bytes::BytesMut;
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use shared::database::PGPool;
+use shared::error::AppError;
+use shared::media::{
+    MEDIA_MAX_UPLOAD_BYTES, MediaStore, get_media, get_media_variant, storage_error,
+};
+use shared::validate::ValidationErrors;
+
+#[derive(Deserialize)]
+struct MediaQuery {
+    size: Option<u32>,
+}
+
+#[get("/media/{media_id}")]
+pub async fn get_media_object(
+    pool: web::Data<PGPool>,
+    store: web::Data<dyn MediaStore>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<MediaQuery>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /profile/media from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    let media_id = Uuid::parse_str(path.trim()).map_err(|_| AppError::invalid_uuid("media_id"))?;
+
+    let media = match query.size {
+        Some(size) => get_media_variant(&pool, media_id, size).await?,
+        None => get_media(&pool, media_id).await?,
+    };
+
+    let bytes = store
+        .get(&media.content_hash)
+        .await
+        .map_err(storage_error)?
+        .ok_or_else(|| AppError::not_found("media_not_found", "media not found"))?;
+
+    let content_type = media
+        .content_type
+        .parse()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+
+    // objects are content-addressed, so they never change once stored
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType(content_type))
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(31_536_000),
+            CacheDirective::Extension("immutable".to_string(), None),
+        ]))
+        .insert_header(ETag(EntityTag::new_strong(media.content_hash)))
+        .body(bytes))
+}
+
+/// Reads the `file` field of a multipart body, stopping as soon as it exceeds
+/// `MEDIA_MAX_UPLOAD_BYTES` so oversized uploads are never fully buffered.
+pub(crate) async fn read_file_field(
+    payload: &mut Multipart,
+    errors: &mut ValidationErrors,
+) -> Result<Option<BytesMut>, AppError> {
+    let max_bytes = *MEDIA_MAX_UPLOAD_BYTES;
+
+    while let Some(mut field) = payload.try_next().await.map_err(invalid_multipart)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let mut bytes = BytesMut::new();
+
+        while let Some(chunk) = field.try_next().await.map_err(invalid_multipart)? {
+            if bytes.len() + chunk.len() > max_bytes {
+                errors.add_with_params(
+                    "file",
+                    "too_large",
+                    [("max_bytes", max_bytes.into())].into(),
+                );
+                return Ok(None);
+            }
+
+            bytes.extend_from_slice(&chunk);
+        }
+
+        return Ok(Some(bytes));
+    }
+
+    Ok(None)
+}
+
+fn invalid_multipart(e: actix_multipart::MultipartError) -> AppError {
+    AppError::bad_request("invalid_body", e.to_string())
+}
@@ -1,23 +1,36 @@
 use std::collections::HashMap;
 
-use actix_web::http::header::ContentType;
-use actix_web::{HttpRequest, HttpResponse, Responder, get, patch, web};
+use actix_web::http::header::{
+    CacheControl, CacheDirective, ContentType, ETag, IfMatch, IfNoneMatch,
+};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, get, patch, web};
 use chrono::Utc;
 use serde_json::to_string;
 use uuid::Uuid;
 
+use shared::avatar::AVATAR_KIND;
 use shared::csrf::verify_csrf_token;
 use shared::database::PGPool;
+use shared::error::AppError;
 use shared::jwt::{JwtTokenKind, extract_user_id};
+use shared::media::{media_owned_by, media_url};
 use shared::models::UpdateUser;
-use shared::profile::{apply_profile_update, get_user_by_id};
+use shared::profile::{
+    ProfileLookup, apply_profile_update, get_public_profile, get_user_by_id, profile_etag,
+};
+use shared::username::{fold_username, normalise_username, username_skeleton};
 use shared::validate::{
-    validate_bio, validate_email, validate_new_username, validate_phone_number,
-    validate_profile_pic,
+    ValidationErrors, validate_bio, validate_display_name, validate_email_format,
+    validate_existing_username, validate_phone_number_format, validate_pronouns,
+    validate_status_emoji, validate_status_expires_at, validate_status_text, validate_timezone,
+    validate_unique_fields, validate_username_format,
 };
 
 #[get("/self")]
-pub async fn get_profile(pool: web::Data<PGPool>, req: HttpRequest) -> impl Responder {
+pub async fn get_profile(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
     println!(
         "{:?}: GET /profile/self from {:?}",
         Utc::now().timestamp() as usize,
@@ -25,38 +38,57 @@ pub async fn get_profile(pool: web::Data<PGPool>, req: HttpRequest) -> impl Resp
     );
 
     // extract user id from access token
-    let user_id = match extract_user_id(&req, JwtTokenKind::ACCESS) {
-        Ok(id) => id,
-        Err(resp) => return resp,
-    };
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
 
-    match get_user_by_id(pool, &user_id).await {
-        Ok(user) => {
-            let mut map = HashMap::new();
-            map.insert("username", user.username);
-            map.insert("email", user.email);
-            map.insert("phone_number", user.phone_number);
-            map.insert("profile_pic", user.profile_pic.unwrap_or("".to_string()));
-            map.insert("bio", user.bio.unwrap_or("".to_string()));
+    let mut user = get_user_by_id(pool, &user_id).await?;
 
-            let json_str = to_string(&map).unwrap();
+    let etag = profile_etag(user.updated_at, user.status_expires_at);
 
-            HttpResponse::Ok()
-                .content_type(ContentType::json())
-                .body(json_str)
-        }
-        Err(e) => {
-            eprintln!(
-                "{:?}: User profile fetching failed: {:?}",
-                Utc::now().timestamp() as usize,
-                e
-            );
-
-            HttpResponse::Unauthorized()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"User not found"}"#)
-        }
+    user.clear_expired_status();
+
+    let not_modified = match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => false,
+    };
+
+    if not_modified {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
     }
+
+    let mut map = HashMap::new();
+    map.insert("username", user.username);
+    map.insert("email", user.email);
+    map.insert("phone_number", user.phone_number);
+    map.insert(
+        "profile_pic",
+        user.profile_pic_id.map(media_url).unwrap_or_default(),
+    );
+    map.insert("bio", user.bio.unwrap_or_default());
+    map.insert("display_name", user.display_name.unwrap_or_default());
+    map.insert("pronouns", user.pronouns.unwrap_or_default());
+    map.insert("status_text", user.status_text.unwrap_or_default());
+    map.insert("status_emoji", user.status_emoji.unwrap_or_default());
+    map.insert(
+        "status_expires_at",
+        user.status_expires_at
+            .map(|expires_at| expires_at.to_rfc3339())
+            .unwrap_or_default(),
+    );
+    map.insert("timezone", user.timezone.unwrap_or_default());
+
+    let json_str = to_string(&map).unwrap();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .insert_header(CacheControl(vec![
+            CacheDirective::Private,
+            CacheDirective::NoCache,
+        ]))
+        .insert_header(ETag(etag))
+        .body(json_str))
 }
 
 #[patch("/self")]
@@ -64,132 +96,184 @@ pub async fn patch_profile(
     pool: web::Data<PGPool>,
     req_body: web::Json<UpdateUser>,
     req: HttpRequest,
-) -> impl Responder {
+) -> Result<HttpResponse, AppError> {
     println!(
         "{:?}: PATCH /profile/self from {:?}",
         Utc::now().timestamp() as usize,
         req.peer_addr()
     );
 
-    let verify_csrf = verify_csrf_token(&req);
-
-    if !verify_csrf {
-        return HttpResponse::Unauthorized()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"csrf failed"}"#);
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
     }
 
     // extract user id from access token
-    let user_id = match extract_user_id(&req, JwtTokenKind::ACCESS) {
-        Ok(id) => id,
-        Err(resp) => return resp,
-    };
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
 
-    let user_uuid = match Uuid::parse_str(&user_id) {
-        Ok(value) => value,
-        Err(_) => {
-            return HttpResponse::Unauthorized()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"invalid access token"}"#);
-        }
-    };
+    let user_uuid = Uuid::parse_str(&user_id)
+        .map_err(|_| AppError::unauthorized("invalid_token", "invalid access token"))?;
 
     let mut data = req_body.into_inner();
 
+    // collect every invalid field so the client can report them all at once
+    let mut errors = ValidationErrors::default();
+
     if let Some(username) = data.username.as_mut() {
-        *username = username.trim().to_string();
-
-        match validate_new_username(pool.clone(), &username).await {
-            Ok(valid) => {
-                if !valid {
-                    return HttpResponse::BadRequest()
-                        .content_type(ContentType::json())
-                        .body(r#"{"detail":"invalid username format"}"#);
-                }
-            }
-            Err(_) => {
-                return HttpResponse::BadRequest()
-                    .content_type(ContentType::json())
-                    .body(r#"{"detail":"username taken"}"#);
-            }
-        };
+        *username = normalise_username(username);
+
+        validate_username_format(username, &mut errors);
     }
 
     if let Some(email) = data.email.as_mut() {
         *email = email.trim().to_string();
 
-        match validate_email(pool.clone(), &email).await {
-            Ok(valid) => {
-                if !valid {
-                    return HttpResponse::BadRequest()
-                        .content_type(ContentType::json())
-                        .body(r#"{"detail":"invalid email format"}"#);
-                }
-            }
-            Err(_) => {
-                return HttpResponse::BadRequest()
-                    .content_type(ContentType::json())
-                    .body(r#"{"detail":"email taken"}"#);
-            }
-        };
+        validate_email_format(email, &mut errors);
     }
 
-    if let Some(phone_number) = data.phone_number.as_mut() {
-        *phone_number = phone_number.trim().to_string();
-
-        match validate_phone_number(pool.clone(), &phone_number).await {
-            Ok(valid) => {
-                if !valid {
-                    return HttpResponse::BadRequest()
-                        .content_type(ContentType::json())
-                        .body(r#"{"detail":"invalid phone number format"}"#);
-                }
-            }
-            Err(_) => {
-                return HttpResponse::BadRequest()
-                    .content_type(ContentType::json())
-                    .body(r#"{"detail":"phone number taken"}"#);
-            }
-        };
-    }
+    data.phone_number = data.phone_number.map(|phone_number| {
+        validate_phone_number_format(&phone_number, &mut errors).unwrap_or(phone_number)
+    });
 
     if let Some(bio) = data.bio.as_mut() {
         *bio = bio.trim().to_string();
 
-        if !validate_bio(&bio) {
-            return HttpResponse::BadRequest()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"invalid bio format"}"#);
-        }
+        validate_bio(bio, &mut errors);
+    }
+
+    if let Some(Some(display_name)) = data.display_name.as_mut() {
+        *display_name = display_name.trim().to_string();
+
+        validate_display_name(display_name, &mut errors);
+    }
+
+    if let Some(Some(pronouns)) = data.pronouns.as_mut() {
+        *pronouns = pronouns.trim().to_string();
+
+        validate_pronouns(pronouns, &mut errors);
+    }
+
+    if let Some(Some(status_text)) = data.status_text.as_mut() {
+        *status_text = status_text.trim().to_string();
+
+        validate_status_text(status_text, &mut errors);
+    }
+
+    if let Some(Some(status_emoji)) = data.status_emoji.as_mut() {
+        *status_emoji = status_emoji.trim().to_string();
+
+        validate_status_emoji(status_emoji, &mut errors);
+    }
+
+    if let Some(Some(status_expires_at)) = data.status_expires_at {
+        validate_status_expires_at(status_expires_at, &mut errors);
+    }
+
+    if let Some(Some(timezone)) = data.timezone.as_mut() {
+        *timezone = timezone.trim().to_string();
+
+        validate_timezone(timezone, &mut errors);
     }
 
-    if let Some(profile_pic) = data.profile_pic.as_mut() {
-        if !validate_profile_pic(&profile_pic) {
-            return HttpResponse::BadRequest()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"invalid profile pic format"}"#);
+    if let Some(profile_pic_id) = data.profile_pic_id {
+        let owned = media_owned_by(&pool, profile_pic_id, user_uuid, AVATAR_KIND).await?;
+
+        if !owned {
+            errors.add("profile_pic_id", "invalid_media");
         }
     }
 
+    validate_unique_fields(
+        pool.clone(),
+        Some(user_uuid),
+        data.username.as_deref(),
+        data.email.as_deref(),
+        data.phone_number.as_deref(),
+        &mut errors,
+    )
+    .await?;
+
+    errors.into_result()?;
+
     let changes = UpdateUser {
+        username_normalised: data.username.as_deref().map(fold_username),
+        username_skeleton: data.username.as_deref().map(username_skeleton),
         username: data.username,
         email: data.email,
+        // a new number replaces one the backfill could not convert
+        phone_number_invalid: data.phone_number.as_ref().map(|_| false),
         phone_number: data.phone_number,
         bio: data.bio,
-        profile_pic: data.profile_pic,
+        profile_pic_id: data.profile_pic_id,
+        display_name: data.display_name,
+        pronouns: data.pronouns,
+        status_text: data.status_text,
+        status_emoji: data.status_emoji,
+        status_expires_at: data.status_expires_at,
+        timezone: data.timezone,
     };
 
-    match apply_profile_update(pool, user_uuid, changes).await {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(e) => {
-            eprintln!(
-                "{:?}: Failed to update user: {:?}",
-                Utc::now().timestamp(),
-                e
-            );
-            HttpResponse::BadRequest()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"failed to update user"}"#)
-        }
+    // `If-Match: *` only asks for the profile to exist, which it always does here
+    let if_match = match req.get_header::<IfMatch>() {
+        Some(IfMatch::Items(tags)) => Some(tags),
+        _ => None,
+    };
+
+    let etag = apply_profile_update(pool, user_uuid, changes, if_match.as_deref()).await?;
+
+    Ok(HttpResponse::Ok().insert_header(ETag(etag)).finish())
+}
+
+#[get("/id/{user_id}")]
+pub async fn get_profile_by_id(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /profile/id from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let viewer_uuid = Uuid::parse_str(&user_id)
+        .map_err(|_| AppError::unauthorized("invalid_token", "invalid access token"))?;
+
+    let target_uuid =
+        Uuid::parse_str(path.trim()).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    let profile = get_public_profile(pool, viewer_uuid, ProfileLookup::Id(target_uuid)).await?;
+
+    Ok(HttpResponse::Ok().json(profile))
+}
+
+#[get("/{username}")]
+pub async fn get_profile_by_username(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /profile/{{username}} from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let viewer_uuid = Uuid::parse_str(&user_id)
+        .map_err(|_| AppError::unauthorized("invalid_token", "invalid access token"))?;
+
+    let username = normalise_username(&path);
+
+    if !validate_existing_username(&username) {
+        return Err(AppError::not_found("user_not_found", "user not found"));
     }
+
+    let profile = get_public_profile(pool, viewer_uuid, ProfileLookup::Username(&username)).await?;
+
+    Ok(HttpResponse::Ok().json(profile))
 }
@@ -1,10 +1,32 @@
-use crate::profile::{get_profile, patch_profile};
+use crate::admin::{
+    delete_reserved_username_grant, get_reserved_username_grants, post_reserved_username_grant,
+};
+use crate::avatar::{delete_avatar, put_avatar};
+use crate::media::get_media_object;
+use crate::profile::{get_profile, get_profile_by_id, get_profile_by_username, patch_profile};
+use crate::search::get_search;
+use crate::settings::{get_user_settings, patch_user_settings};
 use actix_web::web;
 
-static SCOPE_HANDLERS: &[(&str, fn(&mut web::ServiceConfig))] = &[("profile", routes)];
+type ScopeHandler = (&'static str, fn(&mut web::ServiceConfig));
+
+static SCOPE_HANDLERS: &[ScopeHandler] = &[("profile", routes)];
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(get_profile).service(patch_profile);
+    cfg.service(get_profile)
+        .service(patch_profile)
+        .service(put_avatar)
+        .service(delete_avatar)
+        .service(get_media_object)
+        .service(get_reserved_username_grants)
+        .service(post_reserved_username_grant)
+        .service(delete_reserved_username_grant)
+        .service(get_search)
+        .service(get_user_settings)
+        .service(patch_user_settings)
+        .service(get_profile_by_id)
+        // catch-all, keep it after every other single segment route
+        .service(get_profile_by_username);
 }
 
 pub fn apply_routes(cfg: &mut web::ServiceConfig) {
@@ -0,0 +1,51 @@
+use actix_web::{HttpRequest, HttpResponse, get, web};
+use chrono::Utc;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use shared::database::PGPool;
+use shared::error::AppError;
+use shared::jwt::{JwtTokenKind, extract_user_id};
+use shared::search::{
+    SEARCH_DEFAULT_PAGE_SIZE, SEARCH_RATE_LIMITER, search_users, validate_search_params,
+};
+use shared::validate::ValidationErrors;
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    page: Option<u32>,
+    per_page: Option<u32>,
+}
+
+#[get("/search")]
+pub async fn get_search(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /profile/search from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let user_uuid = Uuid::parse_str(&user_id)
+        .map_err(|_| AppError::unauthorized("invalid_token", "invalid access token"))?;
+
+    SEARCH_RATE_LIMITER.check(user_uuid)?;
+
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(SEARCH_DEFAULT_PAGE_SIZE);
+
+    let mut errors = ValidationErrors::default();
+    let term = validate_search_params(&query.q, page, per_page, &mut errors);
+    errors.into_result()?;
+
+    let results = search_users(pool, user_uuid, term, page, per_page).await?;
+
+    Ok(HttpResponse::Ok().json(results))
+}
@@ -0,0 +1,61 @@
+use actix_web::{HttpRequest, HttpResponse, get, patch, web};
+use chrono::Utc;
+use serde_json::Value;
+use uuid::Uuid;
+
+use shared::csrf::verify_csrf_token;
+use shared::database::PGPool;
+use shared::error::AppError;
+use shared::jwt::{JwtTokenKind, extract_user_id};
+use shared::settings::{get_settings, patch_settings};
+
+#[get("/settings")]
+pub async fn get_user_settings(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: GET /profile/settings from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let user_uuid = Uuid::parse_str(&user_id)
+        .map_err(|_| AppError::unauthorized("invalid_token", "invalid access token"))?;
+
+    let settings = get_settings(pool, user_uuid).await?;
+
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+/// Takes a JSON merge patch (RFC 7396), sent as `application/merge-patch+json`
+/// or `application/json`, and returns the settings after applying it.
+#[patch("/settings")]
+pub async fn patch_user_settings(
+    pool: web::Data<PGPool>,
+    req: HttpRequest,
+    req_body: web::Json<Value>,
+) -> Result<HttpResponse, AppError> {
+    println!(
+        "{:?}: PATCH /profile/settings from {:?}",
+        Utc::now().timestamp() as usize,
+        req.peer_addr()
+    );
+
+    if !verify_csrf_token(&req) {
+        return Err(AppError::csrf_failed());
+    }
+
+    // extract user id from access token
+    let user_id = extract_user_id(&req, JwtTokenKind::ACCESS)?;
+
+    let user_uuid = Uuid::parse_str(&user_id)
+        .map_err(|_| AppError::unauthorized("invalid_token", "invalid access token"))?;
+
+    let settings = patch_settings(pool, user_uuid, req_body.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(settings))
+}
@@ -0,0 +1,346 @@
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use base64::prelude::*;
+use bytes::Bytes;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::{PngDecoder, PngEncoder};
+use image::codecs::webp::{WebPDecoder, WebPEncoder};
+use image::imageops::FilterType;
+use image::{
+    AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader, ImageResult, Limits,
+};
+use uuid::Uuid;
+
+use super::config::env_or;
+use super::database::PGPool;
+use super::error::AppError;
+use super::media::{MEDIA_MAX_UPLOAD_BYTES, MediaMetadata, MediaStore, store_media};
+use super::models::Media;
+use super::validate::ValidationErrors;
+
+pub const AVATAR_KIND: &str = "avatar";
+
+/// Square sizes every avatar is produced in, largest first. The largest is the
+/// object `users.profile_pic_id` points at, the others are its variants.
+pub const AVATAR_SIZES: [u32; 3] = [256, 64, 32];
+
+/// Format avatars are re-encoded to. Both are lossless, so the only loss is
+/// the resize itself.
+#[derive(Clone, Copy)]
+pub enum AvatarFormat {
+    WebP,
+    Png,
+}
+
+impl AvatarFormat {
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            AvatarFormat::WebP => "image/webp",
+            AvatarFormat::Png => "image/png",
+        }
+    }
+}
+
+impl FromStr for AvatarFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "webp" => Ok(AvatarFormat::WebP),
+            "png" => Ok(AvatarFormat::Png),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Avatar rules, configurable through `AVATAR_MIN_DIMENSION`,
+/// `AVATAR_MAX_DIMENSION` and `AVATAR_FORMAT`. Uploads are also capped at
+/// `MEDIA_MAX_UPLOAD_BYTES`.
+pub struct AvatarPolicy {
+    pub min_dimension: u32,
+    pub max_dimension: u32,
+    pub format: AvatarFormat,
+}
+
+impl AvatarPolicy {
+    pub fn from_env() -> Self {
+        AvatarPolicy {
+            min_dimension: env_or("AVATAR_MIN_DIMENSION", 32),
+            max_dimension: env_or("AVATAR_MAX_DIMENSION", 4096),
+            format: env_or("AVATAR_FORMAT", AvatarFormat::WebP),
+        }
+    }
+}
+
+pub static AVATAR_POLICY: LazyLock<AvatarPolicy> = LazyLock::new(AvatarPolicy::from_env);
+
+/// One re-encoded size of an avatar.
+pub struct AvatarVariant {
+    pub size: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// Decodes an uploaded avatar, checks it against the policy and produces every
+/// size in `AVATAR_SIZES`. Images are rotated according to their EXIF
+/// orientation, centre-cropped to a square and re-encoded from raw pixels, so
+/// EXIF, GPS and any other metadata in the upload are dropped. Problems are
+/// reported on the `file` field.
+pub fn process_avatar(bytes: &[u8], errors: &mut ValidationErrors) -> Option<Vec<AvatarVariant>> {
+    let policy = &*AVATAR_POLICY;
+    let max_bytes = *MEDIA_MAX_UPLOAD_BYTES;
+
+    if bytes.len() > max_bytes {
+        errors.add_with_params(
+            "file",
+            "too_large",
+            BTreeMap::from([("max_bytes", max_bytes.into())]),
+        );
+        return None;
+    }
+
+    let format = match image::guess_format(bytes) {
+        Ok(
+            format @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP),
+        ) => format,
+        _ => {
+            errors.add("file", "unsupported_media_type");
+            return None;
+        }
+    };
+
+    let Ok((width, height)) =
+        ImageReader::with_format(Cursor::new(bytes), format).into_dimensions()
+    else {
+        errors.add("file", "invalid_image");
+        return None;
+    };
+
+    if width.min(height) < policy.min_dimension || width.max(height) > policy.max_dimension {
+        errors.add_with_params(
+            "file",
+            "dimensions",
+            BTreeMap::from([
+                ("min", policy.min_dimension.into()),
+                ("max", policy.max_dimension.into()),
+            ]),
+        );
+        return None;
+    }
+
+    // only checked once the dimensions are known to be in range, since telling
+    // whether a GIF is animated means decoding its frames
+    match is_animated(bytes, format, policy.max_dimension) {
+        Ok(false) => {}
+        Ok(true) => {
+            errors.add("file", "animated");
+            return None;
+        }
+        Err(_) => {
+            errors.add("file", "invalid_image");
+            return None;
+        }
+    }
+
+    let Ok(image) = decode_oriented(bytes, format, policy.max_dimension) else {
+        errors.add("file", "invalid_image");
+        return None;
+    };
+
+    let side = image.width().min(image.height());
+    let square = image.crop_imm(
+        (image.width() - side) / 2,
+        (image.height() - side) / 2,
+        side,
+        side,
+    );
+
+    let mut variants = Vec::with_capacity(AVATAR_SIZES.len());
+
+    for size in AVATAR_SIZES {
+        let resized = DynamicImage::ImageRgba8(
+            square
+                .resize_exact(size, size, FilterType::Lanczos3)
+                .to_rgba8(),
+        );
+
+        let mut encoded = Vec::new();
+        let result = match policy.format {
+            AvatarFormat::WebP => {
+                resized.write_with_encoder(WebPEncoder::new_lossless(&mut encoded))
+            }
+            AvatarFormat::Png => resized.write_with_encoder(PngEncoder::new(&mut encoded)),
+        };
+
+        if result.is_err() {
+            errors.add("file", "invalid_image");
+            return None;
+        }
+
+        variants.push(AvatarVariant {
+            size,
+            bytes: encoded,
+        });
+    }
+
+    Some(variants)
+}
+
+fn is_animated(bytes: &[u8], format: ImageFormat, max_dimension: u32) -> ImageResult<bool> {
+    match format {
+        ImageFormat::Gif => {
+            let mut decoder = GifDecoder::new(Cursor::new(bytes))?;
+            decoder.set_limits(decode_limits(max_dimension))?;
+
+            Ok(decoder.into_frames().take(2).count() > 1)
+        }
+        ImageFormat::Png => PngDecoder::new(Cursor::new(bytes))?.is_apng(),
+        ImageFormat::WebP => Ok(WebPDecoder::new(Cursor::new(bytes))?.has_animation()),
+        _ => Ok(false),
+    }
+}
+
+/// Limits guarding every decode of an upload against decompression bombs.
+fn decode_limits(max_dimension: u32) -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(max_dimension);
+    limits.max_image_height = Some(max_dimension);
+    limits
+}
+
+/// Decodes within `decode_limits`, then applies the EXIF orientation since the
+/// metadata carrying it is about to be dropped.
+fn decode_oriented(
+    bytes: &[u8],
+    format: ImageFormat,
+    max_dimension: u32,
+) -> ImageResult<DynamicImage> {
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(decode_limits(max_dimension));
+
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    Ok(image)
+}
+
+/// Stores every variant of a processed avatar, returning the largest, which
+/// the smaller ones are linked to.
+pub async fn store_avatar(
+    pool: &PGPool,
+    store: &dyn MediaStore,
+    owner: Uuid,
+    variants: Vec<AvatarVariant>,
+) -> Result<Media, AppError> {
+    let mime_type = AVATAR_POLICY.format.mime_type();
+    let mut original: Option<Media> = None;
+
+    for variant in variants {
+        let stored = store_media(
+            pool,
+            store,
+            owner,
+            Bytes::from(variant.bytes),
+            MediaMetadata {
+                kind: AVATAR_KIND,
+                mime_type,
+                dimensions: Some((variant.size, variant.size)),
+                variant_of: original.as_ref().map(|media| media.id),
+            },
+        )
+        .await?;
+
+        original.get_or_insert(stored);
+    }
+
+    original.ok_or_else(|| AppError::internal("avatar_failed", "internal server error"))
+}
+
+/// How many legacy profile pictures are held in memory at once while moving
+/// them to media storage.
+const LEGACY_PROFILE_PIC_BATCH_SIZE: i64 = 50;
+
+/// Moves profile pictures stored as base64 data URLs in `users.legacy_profile_pic`
+/// into media storage, running them through the avatar pipeline a batch at a
+/// time. Rows that do not hold a usable image are reported and flagged with
+/// `legacy_profile_pic_invalid`, so later startups skip them.
+pub async fn backfill_legacy_profile_pics(
+    pool: &PGPool,
+    store: &dyn MediaStore,
+) -> Result<usize, AppError> {
+    use crate::schema::users::dsl::*;
+
+    let mut conn = pool.get().await?;
+
+    let mut moved = 0;
+    let mut last_id: Option<Uuid> = None;
+
+    loop {
+        let mut query = users
+            .filter(legacy_profile_pic.is_not_null())
+            .filter(legacy_profile_pic_invalid.eq(false))
+            .order(id.asc())
+            .limit(LEGACY_PROFILE_PIC_BATCH_SIZE)
+            .select((id, legacy_profile_pic.assume_not_null()))
+            .into_boxed();
+
+        if let Some(last_id) = last_id {
+            query = query.filter(id.gt(last_id));
+        }
+
+        let batch: Vec<(Uuid, String)> = query.load(&mut conn).await?;
+
+        let Some((batch_last_id, _)) = batch.last() else {
+            break;
+        };
+        last_id = Some(*batch_last_id);
+
+        for (user_id, data_url) in &batch {
+            // remove the "data:image/..." prefix
+            let base64_data = data_url
+                .split_once(',')
+                .map_or(data_url.as_str(), |(_, data)| data);
+
+            let variants = BASE64_STANDARD
+                .decode(base64_data)
+                .ok()
+                .and_then(|bytes| process_avatar(&bytes, &mut ValidationErrors::default()));
+
+            let Some(variants) = variants else {
+                eprintln!(
+                    "{:?}: WARNING: profile picture of user {} is not a usable image, flagging it",
+                    Utc::now().timestamp() as usize,
+                    user_id
+                );
+
+                diesel::update(users.filter(id.eq(user_id)))
+                    .set(legacy_profile_pic_invalid.eq(true))
+                    .execute(&mut conn)
+                    .await?;
+                continue;
+            };
+
+            let avatar = store_avatar(pool, store, *user_id, variants).await?;
+
+            diesel::update(users.filter(id.eq(user_id)))
+                .set((
+                    profile_pic_id.eq(avatar.id),
+                    legacy_profile_pic.eq(None::<String>),
+                ))
+                .execute(&mut conn)
+                .await?;
+
+            moved += 1;
+        }
+    }
+
+    Ok(moved)
+}
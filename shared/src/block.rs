@@ -0,0 +1,42 @@
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+/// Whether `blocker` has blocked `blocked`.
+pub async fn has_blocked(
+    conn: &mut AsyncPgConnection,
+    blocker_id: Uuid,
+    blocked_id: Uuid,
+) -> QueryResult<bool> {
+    use crate::schema::user_block::dsl::*;
+
+    diesel::select(exists(
+        user_block
+            .filter(blocker.eq(blocker_id))
+            .filter(blocked.eq(blocked_id)),
+    ))
+    .get_result(conn)
+    .await
+}
+
+/// Whether either user has blocked the other. Blocked users are hidden from
+/// each other everywhere, so this is the check most features want.
+pub async fn is_blocked_between(
+    conn: &mut AsyncPgConnection,
+    user_a: Uuid,
+    user_b: Uuid,
+) -> QueryResult<bool> {
+    use crate::schema::user_block::dsl::*;
+
+    diesel::select(exists(
+        user_block.filter(
+            blocker
+                .eq(user_a)
+                .and(blocked.eq(user_b))
+                .or(blocker.eq(user_b).and(blocked.eq(user_a))),
+        ),
+    ))
+    .get_result(conn)
+    .await
+}
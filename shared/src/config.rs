@@ -0,0 +1,17 @@
+use dotenv::dotenv;
+use std::env;
+use std::str::FromStr;
+
+/// Reads `key` from the environment (or `.env`), falling back to `default` when
+/// the variable is unset or cannot be parsed.
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    dotenv().ok();
+
+    match env::var(key) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            eprintln!("WARNING: invalid value for {}, using the default", key);
+            default
+        }),
+        Err(_) => default,
+    }
+}
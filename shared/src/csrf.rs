@@ -8,14 +8,9 @@ pub fn generate_csrf_token() -> String {
         .generate_token_pair(None, 300)
         .expect("couldn't generate token/cookie pair");
 
-    let token_str = token.b64_string();
-
-    token_str
+    token.b64_string()
 }
 
 pub fn verify_csrf_token(req: &HttpRequest) -> bool {
-    match req.headers().get("X-CSRF-Token") {
-        Some(_) => true,
-        None => false,
-    }
+    req.headers().get("X-CSRF-Token").is_some()
 }
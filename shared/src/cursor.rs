@@ -0,0 +1,38 @@
+use base64::prelude::*;
+use uuid::Uuid;
+
+use super::error::AppError;
+
+/// Opaque keyset pagination cursor: the sort it was issued for, the sort key of
+/// the last row returned and that row's id to break ties. Clients pass it back
+/// unchanged to get the rows after it.
+pub struct Cursor {
+    pub key: String,
+    pub id: Uuid,
+}
+
+pub fn encode_cursor(sort: &str, key: &str, id: Uuid) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(format!("{}|{}|{}", sort, id, key))
+}
+
+/// Decodes a cursor issued by `encode_cursor` for the same `sort`.
+pub fn decode_cursor(cursor: &str, sort: &str) -> Result<Cursor, AppError> {
+    let invalid = || AppError::bad_request("invalid_cursor", "invalid cursor");
+
+    let decoded = BASE64_URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(invalid)?;
+
+    // the key goes last so it may contain the separator itself
+    let mut parts = decoded.splitn(3, '|');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(cursor_sort), Some(id), Some(key)) if cursor_sort == sort => Ok(Cursor {
+            key: key.to_string(),
+            id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        }),
+        _ => Err(invalid()),
+    }
+}
@@ -2,7 +2,9 @@ use diesel_async::AsyncPgConnection;
 use diesel_async::pooled_connection::AsyncDieselConnectionManager;
 use diesel_async::pooled_connection::deadpool::{BuildError, Pool};
 use dotenv::dotenv;
-use std::{env, usize};
+use redis::RedisResult;
+use redis::aio::ConnectionManager;
+use std::env;
 
 pub type PGPool = Pool<AsyncPgConnection>;
 
@@ -18,3 +20,15 @@ pub async fn create_database_pool(max_size: usize) -> Result<PGPool, BuildError>
 
     Ok(pool)
 }
+
+/// Connects to the Redis instance at `REDIS_URL`. The manager reconnects on
+/// its own if the connection drops and is cheap to clone.
+pub async fn create_redis_connection() -> RedisResult<ConnectionManager> {
+    dotenv().ok();
+
+    let redis_url = env::var("REDIS_URL").expect("ERROR: REDIS_URL must be present in '.env'");
+
+    let client = redis::Client::open(redis_url)?;
+
+    ConnectionManager::new(client).await
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+
+use actix_web::error::JsonPayloadError;
+use actix_web::http::StatusCode;
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use chrono::Utc;
+use diesel::result::{DatabaseErrorKind as DieselDbError, Error as DieselError};
+use diesel_async::pooled_connection::deadpool::PoolError;
+use redis::RedisError;
+use serde::Serialize;
+
+use super::jwt::JwtError;
+use super::validate::ValidationErrors;
+
+const PROBLEM_JSON: &str = "application/problem+json";
+const PROBLEM_TYPE_BASE: &str = "https://github.com/tkl-labs/chat-core/problems/";
+
+/// Error type shared by every service. Each variant maps to one HTTP status and
+/// carries a stable, machine-readable `code` alongside a human-readable `detail`.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest {
+        code: &'static str,
+        detail: String,
+    },
+    Validation(ValidationErrors),
+    Unauthorized {
+        code: &'static str,
+        detail: String,
+    },
+    Forbidden {
+        code: &'static str,
+        detail: String,
+    },
+    NotFound {
+        code: &'static str,
+        detail: String,
+    },
+    Conflict {
+        code: &'static str,
+        detail: String,
+    },
+    PreconditionFailed {
+        code: &'static str,
+        detail: String,
+    },
+    TooManyRequests {
+        code: &'static str,
+        detail: String,
+        retry_after: u64,
+    },
+    ServiceUnavailable {
+        code: &'static str,
+        detail: String,
+    },
+    Internal {
+        code: &'static str,
+        detail: String,
+    },
+}
+
+/// RFC 7807 problem details body.
+#[derive(Serialize)]
+struct ProblemDetails<'a> {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: &'a str,
+    status: u16,
+    detail: &'a str,
+    code: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<&'a ValidationErrors>,
+}
+
+impl AppError {
+    pub fn bad_request(code: &'static str, detail: impl Into<String>) -> Self {
+        AppError::BadRequest {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn unauthorized(code: &'static str, detail: impl Into<String>) -> Self {
+        AppError::Unauthorized {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn forbidden(code: &'static str, detail: impl Into<String>) -> Self {
+        AppError::Forbidden {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn not_found(code: &'static str, detail: impl Into<String>) -> Self {
+        AppError::NotFound {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn conflict(code: &'static str, detail: impl Into<String>) -> Self {
+        AppError::Conflict {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn precondition_failed(code: &'static str, detail: impl Into<String>) -> Self {
+        AppError::PreconditionFailed {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    /// `retry_after` is in seconds and sent back in the `Retry-After` header.
+    pub fn too_many_requests(
+        code: &'static str,
+        detail: impl Into<String>,
+        retry_after: u64,
+    ) -> Self {
+        AppError::TooManyRequests {
+            code,
+            detail: detail.into(),
+            retry_after,
+        }
+    }
+
+    pub fn internal(code: &'static str, detail: impl Into<String>) -> Self {
+        AppError::Internal {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn service_unavailable(code: &'static str, detail: impl Into<String>) -> Self {
+        AppError::ServiceUnavailable {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn csrf_failed() -> Self {
+        AppError::unauthorized("csrf_failed", "csrf failed")
+    }
+
+    pub fn invalid_uuid(field: &str) -> Self {
+        AppError::bad_request("invalid_uuid", format!("invalid {} uuid", field))
+    }
+
+    pub fn presence_unavailable() -> Self {
+        AppError::service_unavailable("presence_unavailable", "presence is unavailable")
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest { code, .. }
+            | AppError::Unauthorized { code, .. }
+            | AppError::Forbidden { code, .. }
+            | AppError::NotFound { code, .. }
+            | AppError::Conflict { code, .. }
+            | AppError::PreconditionFailed { code, .. }
+            | AppError::TooManyRequests { code, .. }
+            | AppError::ServiceUnavailable { code, .. }
+            | AppError::Internal { code, .. } => code,
+            AppError::Validation(_) => "validation_failed",
+        }
+    }
+
+    pub fn detail(&self) -> &str {
+        match self {
+            AppError::BadRequest { detail, .. }
+            | AppError::Unauthorized { detail, .. }
+            | AppError::Forbidden { detail, .. }
+            | AppError::NotFound { detail, .. }
+            | AppError::Conflict { detail, .. }
+            | AppError::PreconditionFailed { detail, .. }
+            | AppError::TooManyRequests { detail, .. }
+            | AppError::ServiceUnavailable { detail, .. }
+            | AppError::Internal { detail, .. } => detail,
+            AppError::Validation(_) => "one or more fields are invalid",
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.detail())
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest { .. } | AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden { .. } => StatusCode::FORBIDDEN,
+            AppError::NotFound { .. } => StatusCode::NOT_FOUND,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::ServiceUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+
+        let problem = ProblemDetails {
+            problem_type: format!("{}{}", PROBLEM_TYPE_BASE, self.code()),
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: self.code(),
+            errors: match self {
+                AppError::Validation(errors) => Some(errors),
+                _ => None,
+            },
+        };
+
+        let mut response = HttpResponse::build(status);
+
+        if let AppError::TooManyRequests { retry_after, .. } = self {
+            response.insert_header((RETRY_AFTER, retry_after.to_string()));
+        }
+
+        response.content_type(PROBLEM_JSON).json(problem)
+    }
+}
+
+impl From<DieselError> for AppError {
+    fn from(e: DieselError) -> Self {
+        match e {
+            DieselError::NotFound => AppError::not_found("not_found", "resource not found"),
+            DieselError::DatabaseError(DieselDbError::UniqueViolation, _) => {
+                AppError::conflict("already_exists", "resource already exists")
+            }
+            DieselError::DatabaseError(DieselDbError::ForeignKeyViolation, _) => {
+                AppError::not_found("not_found", "referenced resource not found")
+            }
+            e => {
+                eprintln!(
+                    "{:?}: Database error: {:?}",
+                    Utc::now().timestamp() as usize,
+                    e
+                );
+                AppError::internal("database_error", "internal server error")
+            }
+        }
+    }
+}
+
+impl From<PoolError> for AppError {
+    fn from(e: PoolError) -> Self {
+        eprintln!(
+            "{:?}: Failed to acquire DB connection: {:?}",
+            Utc::now().timestamp() as usize,
+            e
+        );
+        AppError::ServiceUnavailable {
+            code: "database_unavailable",
+            detail: "database connection error".to_string(),
+        }
+    }
+}
+
+impl From<RedisError> for AppError {
+    fn from(e: RedisError) -> Self {
+        eprintln!(
+            "{:?}: Redis error: {:?}",
+            Utc::now().timestamp() as usize,
+            e
+        );
+        AppError::presence_unavailable()
+    }
+}
+
+impl From<JwtError> for AppError {
+    fn from(e: JwtError) -> Self {
+        match e {
+            JwtError::Expired => AppError::unauthorized("token_expired", "access token expired"),
+            JwtError::Invalid => AppError::unauthorized("invalid_token", "invalid access token"),
+            JwtError::Other(err) => {
+                eprintln!("JWT error: {:?}", err);
+                AppError::unauthorized("token_verification_failed", "token verification failed")
+            }
+        }
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for AppError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        eprintln!(
+            "{:?}: Failed to encode token: {:?}",
+            Utc::now().timestamp() as usize,
+            e
+        );
+        AppError::internal("token_encoding_failed", "internal server error")
+    }
+}
+
+/// Error handler for `web::JsonConfig` so malformed request bodies are reported
+/// as problem details like every other error.
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    AppError::bad_request("invalid_body", err.to_string()).into()
+}
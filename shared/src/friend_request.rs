@@ -0,0 +1,36 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use chrono::TimeDelta;
+
+use super::config::env_or;
+
+/// Friend request limits, configurable through `FRIEND_REQUEST_EXPIRY_DAYS`,
+/// `FRIEND_REQUEST_DAILY_LIMIT`, `FRIEND_REQUEST_DECLINE_COOLDOWN_DAYS` and
+/// `FRIEND_REQUEST_SWEEP_INTERVAL_MINUTES`.
+pub struct FriendRequestPolicy {
+    /// How long a request stays pending before the sweep removes it.
+    pub expiry: TimeDelta,
+    /// Requests a user may send in any 24 hours.
+    pub daily_limit: i64,
+    /// How long after a decline the requester has to wait before asking the
+    /// same user again.
+    pub decline_cooldown: TimeDelta,
+    pub sweep_interval: Duration,
+}
+
+impl FriendRequestPolicy {
+    pub fn from_env() -> Self {
+        FriendRequestPolicy {
+            expiry: TimeDelta::days(env_or("FRIEND_REQUEST_EXPIRY_DAYS", 30)),
+            daily_limit: env_or("FRIEND_REQUEST_DAILY_LIMIT", 50),
+            decline_cooldown: TimeDelta::days(env_or("FRIEND_REQUEST_DECLINE_COOLDOWN_DAYS", 7)),
+            sweep_interval: Duration::from_secs(
+                60 * env_or("FRIEND_REQUEST_SWEEP_INTERVAL_MINUTES", 60),
+            ),
+        }
+    }
+}
+
+pub static FRIEND_REQUEST_POLICY: LazyLock<FriendRequestPolicy> =
+    LazyLock::new(FriendRequestPolicy::from_env);
@@ -0,0 +1,94 @@
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+use super::models::CreateFriend;
+use super::schema::friend::dsl::*;
+
+/// Each friendship is stored once with `user1 < user2`, which the table
+/// enforces. Everything in this module takes the two users in any order.
+pub fn ordered(a: Uuid, b: Uuid) -> (Uuid, Uuid) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+pub async fn are_friends(conn: &mut AsyncPgConnection, a: Uuid, b: Uuid) -> QueryResult<bool> {
+    let (low, high) = ordered(a, b);
+
+    diesel::select(exists(friend.filter(user1.eq(low)).filter(user2.eq(high))))
+        .get_result(conn)
+        .await
+}
+
+/// Makes `a` and `b` friends, returning whether they were not already.
+pub async fn add_friendship(conn: &mut AsyncPgConnection, a: Uuid, b: Uuid) -> QueryResult<bool> {
+    let (low, high) = ordered(a, b);
+
+    let inserted = diesel::insert_into(friend)
+        .values(CreateFriend {
+            user1: low,
+            user2: high,
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .await?;
+
+    Ok(inserted > 0)
+}
+
+/// Ends the friendship of `a` and `b`, returning whether there was one. Each
+/// user's favourite flag, nickname and list memberships for the other go too.
+pub async fn remove_friendship(
+    conn: &mut AsyncPgConnection,
+    a: Uuid,
+    b: Uuid,
+) -> QueryResult<bool> {
+    use crate::schema::friend_list::dsl as l;
+    use crate::schema::friend_list_member::dsl as lm;
+    use crate::schema::friend_meta::dsl as m;
+
+    let (low, high) = ordered(a, b);
+
+    let removed = diesel::delete(friend.filter(user1.eq(low)).filter(user2.eq(high)))
+        .execute(conn)
+        .await?;
+
+    diesel::delete(
+        m::friend_meta.filter(
+            m::user_id
+                .eq(a)
+                .and(m::friend_id.eq(b))
+                .or(m::user_id.eq(b).and(m::friend_id.eq(a))),
+        ),
+    )
+    .execute(conn)
+    .await?;
+
+    for (owner, member) in [(a, b), (b, a)] {
+        diesel::delete(
+            lm::friend_list_member
+                .filter(lm::friend_id.eq(member))
+                .filter(
+                    lm::list_id.eq_any(l::friend_list.filter(l::owner_id.eq(owner)).select(l::id)),
+                ),
+        )
+        .execute(conn)
+        .await?;
+    }
+
+    Ok(removed > 0)
+}
+
+/// Ids of everyone `user` is friends with.
+pub async fn friend_ids(conn: &mut AsyncPgConnection, user: Uuid) -> QueryResult<Vec<Uuid>> {
+    let pairs: Vec<(Uuid, Uuid)> = friend
+        .filter(user1.eq(user).or(user2.eq(user)))
+        .select((user1, user2))
+        .load(conn)
+        .await?;
+
+    Ok(pairs
+        .into_iter()
+        .map(|(low, high)| if low == user { high } else { low })
+        .collect())
+}
@@ -1,4 +1,4 @@
-use actix_web::{HttpRequest, HttpResponse, http::header::ContentType};
+use actix_web::HttpRequest;
 use chrono::{Duration, Utc};
 use dotenv::dotenv;
 use jsonwebtoken::{
@@ -7,6 +7,8 @@ use jsonwebtoken::{
 use serde::{Deserialize, Serialize};
 use std::env;
 
+use super::error::AppError;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     // aud: String, // Optional. Audience
@@ -38,16 +40,14 @@ fn create_jwt_claims(user_id: String, token_type: JwtTokenKind) -> Claims {
         JwtTokenKind::REFRESH => (now + Duration::days(7)).timestamp() as usize,
     };
 
-    let claim = Claims {
+    Claims {
         // aud: "http://127.0.0.1:3000",
-        exp: exp,
+        exp,
         iat: now.timestamp() as usize,
         iss: "http://127.0.0.1:8080".to_string(),
         // nbf: now,
         sub: user_id,
-    };
-
-    claim
+    }
 }
 
 pub fn encode_jwt_token(
@@ -116,10 +116,7 @@ pub fn clear_jwt_tokens() -> (String, String) {
     ("".to_string(), "".to_string())
 }
 
-pub fn extract_user_id(
-    req: &HttpRequest,
-    token_kind: JwtTokenKind,
-) -> Result<String, HttpResponse> {
+pub fn extract_user_id(req: &HttpRequest, token_kind: JwtTokenKind) -> Result<String, AppError> {
     let cookie_name = match token_kind {
         JwtTokenKind::ACCESS => "access_token",
         JwtTokenKind::REFRESH => "refresh_token",
@@ -128,26 +125,9 @@ pub fn extract_user_id(
     let jwt_token = req
         .cookie(cookie_name)
         .map(|c| c.value().to_string())
-        .ok_or_else(|| {
-            HttpResponse::Unauthorized()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"missing jwt token"}"#)
-        })?;
-
-    extract_user_id_from_jwt_token(jwt_token, token_kind).map_err(|e| match e {
-        JwtError::Expired => HttpResponse::Unauthorized()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"access token expired"}"#),
-        JwtError::Invalid => HttpResponse::Unauthorized()
-            .content_type(ContentType::json())
-            .body(r#"{"detail":"invalid access token"}"#),
-        JwtError::Other(err) => {
-            eprintln!("JWT error: {:?}", err);
-            HttpResponse::Unauthorized()
-                .content_type(ContentType::json())
-                .body(r#"{"detail":"token verification failed"}"#)
-        }
-    })
+        .ok_or_else(|| AppError::unauthorized("missing_token", "missing jwt token"))?;
+
+    Ok(extract_user_id_from_jwt_token(jwt_token, token_kind)?)
 }
 
 pub fn extract_user_id_from_jwt_token(
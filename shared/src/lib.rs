@@ -1,7 +1,22 @@
+pub mod avatar;
+pub mod block;
+pub mod config;
 pub mod csrf;
+pub mod cursor;
 pub mod database;
+pub mod error;
+pub mod friend_request;
+pub mod friendship;
 pub mod jwt;
+pub mod media;
 pub mod models;
+pub mod password;
+pub mod phone;
+pub mod presence;
 pub mod profile;
+pub mod rate_limit;
 pub mod schema;
+pub mod search;
+pub mod settings;
+pub mod username;
 pub mod validate;
@@ -0,0 +1,381 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+
+use bytes::Bytes;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use futures_util::future::BoxFuture;
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::path::Path as ObjectPath;
+use object_store::{Attribute, Attributes, ObjectStore, PutOptions, PutPayload};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::config::env_or;
+use super::database::PGPool;
+use super::error::AppError;
+use super::models::{CreateMedia, Media};
+
+/// Largest upload accepted, configurable through `MEDIA_MAX_UPLOAD_BYTES`.
+pub static MEDIA_MAX_UPLOAD_BYTES: LazyLock<usize> =
+    LazyLock::new(|| env_or("MEDIA_MAX_UPLOAD_BYTES", 5 * 1024 * 1024));
+
+/// Prefix of the URLs media is served from, configurable through
+/// `MEDIA_PUBLIC_BASE_URL` (e.g. a CDN in front of the bucket).
+static MEDIA_PUBLIC_BASE_URL: LazyLock<String> = LazyLock::new(|| {
+    env_or("MEDIA_PUBLIC_BASE_URL", "/profile/media".to_string())
+        .trim_end_matches('/')
+        .to_string()
+});
+
+/// Backend holding media bytes. Keys are the SHA-256 of the content, so a key
+/// always maps to the same bytes and writing it twice is harmless.
+pub trait MediaStore: Send + Sync {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        bytes: Bytes,
+        content_type: &'a str,
+    ) -> BoxFuture<'a, io::Result<()>>;
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Option<Bytes>>>;
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>>;
+}
+
+/// Stores media on the local filesystem under `root`, fanned out by the first
+/// two bytes of the key so no directory grows too large.
+pub struct LocalMediaStore {
+    root: PathBuf,
+}
+
+impl LocalMediaStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalMediaStore { root: root.into() }
+    }
+
+    fn path_for(&self, key: &str) -> PathBuf {
+        self.root.join(&key[..2]).join(&key[2..4]).join(key)
+    }
+}
+
+impl MediaStore for LocalMediaStore {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        bytes: Bytes,
+        _content_type: &'a str,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let path = self.path_for(key);
+
+            if tokio::fs::try_exists(&path).await? {
+                return Ok(());
+            }
+
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            // write to a temporary file first so readers never see a partial object
+            let tmp_path = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+            tokio::fs::write(&tmp_path, &bytes).await?;
+            tokio::fs::rename(&tmp_path, &path).await
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Option<Bytes>>> {
+        Box::pin(async move {
+            match tokio::fs::read(self.path_for(key)).await {
+                Ok(bytes) => Ok(Some(Bytes::from(bytes))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.path_for(key)).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/// Stores media in an S3-compatible bucket (AWS, MinIO, R2, ...).
+pub struct S3MediaStore {
+    store: AmazonS3,
+}
+
+impl S3MediaStore {
+    pub fn from_env() -> io::Result<Self> {
+        let endpoint = env_or("MEDIA_S3_ENDPOINT", String::new());
+
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(env_or("MEDIA_S3_BUCKET", String::new()))
+            .with_region(env_or("MEDIA_S3_REGION", "us-east-1".to_string()));
+
+        if !endpoint.is_empty() {
+            builder = builder
+                .with_allow_http(endpoint.starts_with("http://"))
+                .with_endpoint(endpoint);
+        }
+
+        let access_key_id = env_or("MEDIA_S3_ACCESS_KEY_ID", String::new());
+        if !access_key_id.is_empty() {
+            builder = builder
+                .with_access_key_id(access_key_id)
+                .with_secret_access_key(env_or("MEDIA_S3_SECRET_ACCESS_KEY", String::new()));
+        }
+
+        let store = builder.build().map_err(io::Error::other)?;
+
+        Ok(S3MediaStore { store })
+    }
+}
+
+impl MediaStore for S3MediaStore {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        bytes: Bytes,
+        content_type: &'a str,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let options = PutOptions {
+                attributes: Attributes::from_iter([(
+                    Attribute::ContentType,
+                    content_type.to_string(),
+                )]),
+                ..Default::default()
+            };
+
+            self.store
+                .put_opts(&ObjectPath::from(key), PutPayload::from(bytes), options)
+                .await
+                .map(|_| ())
+                .map_err(io::Error::other)
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Option<Bytes>>> {
+        Box::pin(async move {
+            match self.store.get(&ObjectPath::from(key)).await {
+                Ok(result) => result.bytes().await.map(Some).map_err(io::Error::other),
+                Err(object_store::Error::NotFound { .. }) => Ok(None),
+                Err(e) => Err(io::Error::other(e)),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            match self.store.delete(&ObjectPath::from(key)).await {
+                Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+                Err(e) => Err(io::Error::other(e)),
+            }
+        })
+    }
+}
+
+/// Builds the backend selected by `MEDIA_STORAGE_BACKEND` (`local` or `s3`).
+pub fn media_store_from_env() -> io::Result<Arc<dyn MediaStore>> {
+    match env_or("MEDIA_STORAGE_BACKEND", "local".to_string()).as_str() {
+        "local" => Ok(Arc::new(LocalMediaStore::new(env_or(
+            "MEDIA_LOCAL_ROOT",
+            "./media".to_string(),
+        )))),
+        "s3" => Ok(Arc::new(S3MediaStore::from_env()?)),
+        other => Err(io::Error::other(format!(
+            "unknown MEDIA_STORAGE_BACKEND {:?}",
+            other
+        ))),
+    }
+}
+
+/// Hex SHA-256 of `bytes`, used as the storage key.
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+pub fn media_url(media_id: Uuid) -> String {
+    format!("{}/{}", *MEDIA_PUBLIC_BASE_URL, media_id)
+}
+
+/// URL of the `size` pixel wide variant of `media_id`.
+pub fn media_variant_url(media_id: Uuid, size: u32) -> String {
+    format!("{}?size={}", media_url(media_id), size)
+}
+
+pub fn storage_error(e: io::Error) -> AppError {
+    eprintln!(
+        "{:?}: Media storage error: {:?}",
+        Utc::now().timestamp() as usize,
+        e
+    );
+    AppError::internal("media_storage_failed", "internal server error")
+}
+
+/// What is recorded about a media object besides its content.
+pub struct MediaMetadata<'a> {
+    pub kind: &'a str,
+    pub mime_type: &'a str,
+    pub dimensions: Option<(u32, u32)>,
+    pub variant_of: Option<Uuid>,
+}
+
+/// Writes `bytes` to the store and records them as a media object owned by
+/// `owner`, returning the existing row when the owner uploaded the same content
+/// before.
+pub async fn store_media(
+    pool: &PGPool,
+    store: &dyn MediaStore,
+    owner: Uuid,
+    bytes: Bytes,
+    metadata: MediaMetadata<'_>,
+) -> Result<Media, AppError> {
+    use crate::schema::media::dsl::*;
+
+    let hash = self::content_hash(&bytes);
+    let size = bytes.len() as i64;
+
+    store
+        .put(&hash, bytes, metadata.mime_type)
+        .await
+        .map_err(storage_error)?;
+
+    let new_media = CreateMedia {
+        owner_id: owner,
+        content_hash: hash,
+        content_type: metadata.mime_type.to_string(),
+        byte_size: size,
+        kind: metadata.kind.to_string(),
+        width: metadata.dimensions.map(|(w, _)| w as i32),
+        height: metadata.dimensions.map(|(_, h)| h as i32),
+        variant_of: metadata.variant_of,
+    };
+
+    let mut conn = pool.get().await?;
+
+    let stored = diesel::insert_into(media)
+        .values(&new_media)
+        .on_conflict((owner_id, content_hash))
+        .do_update()
+        .set((
+            content_type.eq(&new_media.content_type),
+            kind.eq(&new_media.kind),
+            variant_of.eq(new_media.variant_of),
+        ))
+        .returning(Media::as_returning())
+        .get_result(&mut conn)
+        .await?;
+
+    Ok(stored)
+}
+
+pub async fn get_media(pool: &PGPool, media_id: Uuid) -> Result<Media, AppError> {
+    use crate::schema::media::dsl::*;
+
+    let mut conn = pool.get().await?;
+
+    media
+        .filter(id.eq(media_id))
+        .select(Media::as_select())
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::not_found("media_not_found", "media not found"))
+}
+
+/// The variant of `media_id` that is `size` pixels wide, or the object itself
+/// when it already has that width.
+pub async fn get_media_variant(
+    pool: &PGPool,
+    media_id: Uuid,
+    size: u32,
+) -> Result<Media, AppError> {
+    use crate::schema::media::dsl::*;
+
+    let mut conn = pool.get().await?;
+
+    media
+        .filter(id.eq(media_id).or(variant_of.eq(media_id)))
+        .filter(width.eq(size as i32))
+        .select(Media::as_select())
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::not_found("media_not_found", "media not found"))
+}
+
+/// Whether `media_id` is an original (not a variant) of the given `media_kind`
+/// uploaded by `owner`.
+pub async fn media_owned_by(
+    pool: &PGPool,
+    media_id: Uuid,
+    owner: Uuid,
+    media_kind: &str,
+) -> Result<bool, AppError> {
+    use crate::schema::media::dsl::*;
+
+    let mut conn = pool.get().await?;
+
+    let owned = diesel::select(diesel::dsl::exists(
+        media
+            .filter(id.eq(media_id))
+            .filter(owner_id.eq(owner))
+            .filter(kind.eq(media_kind))
+            .filter(variant_of.is_null()),
+    ))
+    .get_result::<bool>(&mut conn)
+    .await?;
+
+    Ok(owned)
+}
+
+/// Deletes `media_id` and its variants once no profile points at it any more,
+/// along with any stored bytes no other media object shares. Returns whether
+/// anything was deleted.
+pub async fn delete_unreferenced_media(
+    pool: &PGPool,
+    store: &dyn MediaStore,
+    media_id: Uuid,
+) -> Result<bool, AppError> {
+    use crate::schema::media::dsl::*;
+    use crate::schema::users::dsl as u;
+
+    let mut conn = pool.get().await?;
+
+    let hashes: Vec<String> = media
+        .filter(id.eq(media_id).or(variant_of.eq(media_id)))
+        .select(content_hash)
+        .load(&mut conn)
+        .await?;
+
+    // variants go with their original through the foreign key
+    let deleted = diesel::delete(media.filter(id.eq(media_id)).filter(diesel::dsl::not(
+        diesel::dsl::exists(u::users.filter(u::profile_pic_id.eq(media_id))),
+    )))
+    .execute(&mut conn)
+    .await?;
+
+    if deleted == 0 {
+        return Ok(false);
+    }
+
+    for hash in hashes {
+        let shared = diesel::select(diesel::dsl::exists(media.filter(content_hash.eq(&hash))))
+            .get_result::<bool>(&mut conn)
+            .await?;
+
+        if !shared {
+            store.delete(&hash).await.map_err(storage_error)?;
+        }
+    }
+
+    Ok(true)
+}
@@ -1,9 +1,16 @@
+use std::io::Write;
+
 use chrono::{DateTime, Utc};
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
 use diesel::prelude::*;
-use serde::{Deserialize, Serialize};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Text;
+use serde::{Deserialize, Deserializer, Serialize};
 use uuid::Uuid;
 
-#[derive(Queryable, Selectable, Serialize)]
+#[derive(Queryable, Selectable)]
 #[diesel(table_name = crate::schema::users)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct User {
@@ -13,9 +20,57 @@ pub struct User {
     pub phone_number: String,
     pub two_factor_auth: bool,
     pub password_hash: String,
-    pub profile_pic: Option<String>,
     pub bio: Option<String>,
     pub created_at: DateTime<Utc>,
+    pub username_normalised: String,
+    pub username_skeleton: Option<String>,
+    pub is_admin: bool,
+    pub profile_pic_id: Option<Uuid>,
+    pub email_verified: bool,
+    pub phone_number_verified: bool,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub display_name: Option<String>,
+    pub pronouns: Option<String>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    pub status_expires_at: Option<DateTime<Utc>>,
+    pub timezone: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl User {
+    pub fn status_expired(&self) -> bool {
+        self.status_expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    /// Drops the custom status once it has expired. Expired statuses are left in
+    /// the database and hidden whenever a user is read.
+    pub fn clear_expired_status(&mut self) {
+        if self.status_expired() {
+            self.status_text = None;
+            self.status_emoji = None;
+            self.status_expires_at = None;
+        }
+    }
+}
+
+/// The columns of a user that may appear wherever other users are listed,
+/// plus the normalised username lists are ordered by.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::users)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PublicUserRow {
+    pub id: Uuid,
+    pub username: String,
+    pub username_normalised: String,
+    pub profile_pic_id: Option<Uuid>,
+    pub display_name: Option<String>,
+    pub pronouns: Option<String>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    pub status_expires_at: Option<DateTime<Utc>>,
+    pub timezone: Option<String>,
 }
 
 #[derive(Queryable, Selectable, Serialize)]
@@ -34,6 +89,48 @@ pub struct CreateFriend {
     pub user2: Uuid,
 }
 
+/// Where a friend request stands. Answered requests are kept so a retried
+/// accept or decline gets the same answer back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum FriendRequestStatus {
+    Pending,
+    Accepted,
+    Declined,
+}
+
+impl FriendRequestStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FriendRequestStatus::Pending => "pending",
+            FriendRequestStatus::Accepted => "accepted",
+            FriendRequestStatus::Declined => "declined",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for FriendRequestStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for FriendRequestStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"pending" => Ok(FriendRequestStatus::Pending),
+            b"accepted" => Ok(FriendRequestStatus::Accepted),
+            b"declined" => Ok(FriendRequestStatus::Declined),
+            other => Err(format!(
+                "unknown friend request status {:?}",
+                String::from_utf8_lossy(other)
+            )
+            .into()),
+        }
+    }
+}
+
 #[derive(Insertable)]
 #[diesel(table_name = crate::schema::friend_request)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -42,11 +139,56 @@ pub struct CreateFriendRequest {
     pub receiver: Uuid,
 }
 
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::user_block)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreateUserBlock {
+    pub blocker: Uuid,
+    pub blocked: Uuid,
+}
+
+#[derive(Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::friend_list)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct FriendList {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub owner_id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::friend_list)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreateFriendList {
+    pub owner_id: Uuid,
+    pub name: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::friend_list_member)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreateFriendListMember {
+    pub list_id: Uuid,
+    pub friend_id: Uuid,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::dismissed_suggestion)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreateDismissedSuggestion {
+    pub user_id: Uuid,
+    pub dismissed: Uuid,
+}
+
 #[derive(Insertable)]
 #[diesel(table_name = crate::schema::users)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct RegisterUser {
     pub username: String,
+    pub username_normalised: String,
+    pub username_skeleton: String,
     pub email: String,
     pub phone_number: String,
     pub password_hash: String,
@@ -64,8 +206,105 @@ pub struct LoginUser {
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct UpdateUser {
     pub username: Option<String>,
+    #[serde(skip)]
+    pub username_normalised: Option<String>,
+    #[serde(skip)]
+    pub username_skeleton: Option<String>,
     pub email: Option<String>,
     pub phone_number: Option<String>,
+    #[serde(skip)]
+    pub phone_number_invalid: Option<bool>,
     pub bio: Option<String>,
-    pub profile_pic: Option<String>,
+    pub profile_pic_id: Option<Uuid>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub pronouns: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub status_text: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub status_emoji: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub status_expires_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub timezone: Option<Option<String>>,
+}
+
+/// A user's private annotations on one of their friends. Only the fields sent
+/// are changed; a `null` nickname removes it.
+#[derive(AsChangeset, Deserialize)]
+#[diesel(table_name = crate::schema::friend_meta)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UpdateFriendMeta {
+    pub favourite: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub nickname: Option<Option<String>>,
+}
+
+/// Tells a field sent as `null` (`Some(None)`, which clears the column) apart
+/// from one left out of the request (`None`, which leaves it unchanged).
+fn deserialize_nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::reserved_username_grant)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ReservedUsernameGrant {
+    pub username_normalised: String,
+    pub user_id: Uuid,
+    pub granted_by: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::reserved_username_grant)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreateReservedUsernameGrant {
+    pub username_normalised: String,
+    pub user_id: Uuid,
+    pub granted_by: Uuid,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::username_history)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreateUsernameHistory {
+    pub user_id: Uuid,
+    pub username: String,
+    pub username_normalised: String,
+}
+
+#[derive(Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::media)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Media {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub content_hash: String,
+    pub content_type: String,
+    pub byte_size: i64,
+    pub created_at: DateTime<Utc>,
+    pub kind: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub variant_of: Option<Uuid>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::media)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreateMedia {
+    pub owner_id: Uuid,
+    pub content_hash: String,
+    pub content_type: String,
+    pub byte_size: i64,
+    pub kind: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub variant_of: Option<Uuid>,
 }
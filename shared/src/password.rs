@@ -0,0 +1,165 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Seek, SeekFrom};
+use std::sync::LazyLock;
+
+use sha1::{Digest, Sha1};
+use zxcvbn::zxcvbn;
+
+use super::config::env_or;
+
+const DEFAULT_BREACHED_CORPUS: &str = include_str!("../data/breached_passwords.txt");
+
+const HASH_PREFIX_LEN: usize = 5;
+
+/// Password rules, configurable through the `PASSWORD_*` variables documented
+/// in `.env.example`. Lengths are counted in characters, not bytes.
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_special: bool,
+    pub allow_non_ascii: bool,
+    pub min_score: u8,
+    pub check_breached: bool,
+    pub breached_corpus_file: String,
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Self {
+        PasswordPolicy {
+            min_length: env_or("PASSWORD_MIN_LENGTH", 12),
+            max_length: env_or("PASSWORD_MAX_LENGTH", 64),
+            require_lowercase: env_or("PASSWORD_REQUIRE_LOWERCASE", true),
+            require_uppercase: env_or("PASSWORD_REQUIRE_UPPERCASE", true),
+            require_digit: env_or("PASSWORD_REQUIRE_DIGIT", true),
+            require_special: env_or("PASSWORD_REQUIRE_SPECIAL", true),
+            allow_non_ascii: env_or("PASSWORD_ALLOW_NON_ASCII", false),
+            min_score: env_or("PASSWORD_MIN_SCORE", 3u8).min(4),
+            check_breached: env_or("PASSWORD_CHECK_BREACHED", true),
+            breached_corpus_file: env_or("PASSWORD_BREACHED_CORPUS_FILE", String::new()),
+        }
+    }
+
+    /// Looks `password` up in the breached-password corpus. Only the first five
+    /// characters of its SHA-1 hash select the range that is scanned, the same
+    /// k-anonymity scheme as the Have I Been Pwned range API, so a full offline
+    /// download can be used as the corpus without loading it into memory.
+    pub fn is_breached(&self, password: &str) -> bool {
+        if !self.check_breached {
+            return false;
+        }
+
+        let hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(HASH_PREFIX_LEN);
+
+        let result = if self.breached_corpus_file.is_empty() {
+            let corpus = DEFAULT_BREACHED_CORPUS.as_bytes();
+            find_in_range(
+                &mut Cursor::new(corpus),
+                corpus.len() as u64,
+                prefix,
+                suffix,
+            )
+        } else {
+            File::open(&self.breached_corpus_file).and_then(|file| {
+                let len = file.metadata()?.len();
+                find_in_range(&mut BufReader::new(file), len, prefix, suffix)
+            })
+        };
+
+        result.unwrap_or_else(|e| {
+            eprintln!(
+                "WARNING: could not read PASSWORD_BREACHED_CORPUS_FILE ({}): {}",
+                self.breached_corpus_file, e
+            );
+            false
+        })
+    }
+}
+
+pub static PASSWORD_POLICY: LazyLock<PasswordPolicy> = LazyLock::new(PasswordPolicy::from_env);
+
+/// Binary searches a corpus sorted by hash (`HASH:COUNT` lines) for the first
+/// line of the `prefix` range, then scans that range for `suffix`.
+fn find_in_range<R: BufRead + Seek>(
+    reader: &mut R,
+    len: u64,
+    prefix: &str,
+    suffix: &str,
+) -> io::Result<bool> {
+    let (mut low, mut high) = (0, len);
+
+    while low < high {
+        let mid = low + (high - low) / 2;
+
+        match line_at(reader, mid)? {
+            Some(line) if line.as_str() < prefix => low = mid + 1,
+            _ => high = mid,
+        }
+    }
+
+    let mut line = line_at(reader, low)?;
+
+    while let Some(current) = line {
+        let Some(rest) = current.strip_prefix(prefix) else {
+            break;
+        };
+
+        if rest.split(':').next() == Some(suffix) {
+            return Ok(true);
+        }
+
+        line = read_line(reader)?;
+    }
+
+    Ok(false)
+}
+
+/// The first full line starting at or after byte `offset`.
+fn line_at<R: BufRead + Seek>(reader: &mut R, offset: u64) -> io::Result<Option<String>> {
+    if offset == 0 {
+        reader.seek(SeekFrom::Start(0))?;
+    } else {
+        reader.seek(SeekFrom::Start(offset - 1))?;
+        reader.read_until(b'\n', &mut Vec::new())?;
+    }
+
+    read_line(reader)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = String::new();
+
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(line.trim_end().to_uppercase()))
+}
+
+/// Estimated strength of a password, on zxcvbn's 0–4 scale.
+#[derive(Debug, Clone, Copy)]
+pub struct PasswordStrength {
+    pub guesses_log10: f64,
+    pub score: u8,
+}
+
+/// Estimates how many guesses an attacker needs for `password` with zxcvbn.
+/// `user_inputs` such as the username and email are split into their parts and
+/// matched as dictionary words alongside zxcvbn's own.
+pub fn estimate_strength(password: &str, user_inputs: &[&str]) -> PasswordStrength {
+    let user_words: Vec<&str> = user_inputs
+        .iter()
+        .flat_map(|input| input.split(['@', '.', '_', '-', ' ']))
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    let entropy = zxcvbn(password, &user_words);
+
+    PasswordStrength {
+        guesses_log10: entropy.guesses_log10(),
+        score: entropy.score().into(),
+    }
+}
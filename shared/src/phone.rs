@@ -0,0 +1,109 @@
+use std::sync::LazyLock;
+
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind as DieselDbError, Error as DieselError};
+use diesel_async::RunQueryDsl;
+use phonenumber::country::Id as Region;
+use phonenumber::metadata::DATABASE;
+use phonenumber::{Mode, Type};
+use uuid::Uuid;
+
+use super::config::env_or;
+use super::database::PGPool;
+use super::error::AppError;
+
+/// Region used to parse numbers entered without a `+` country code, configurable
+/// through `PHONE_DEFAULT_REGION` (an ISO 3166-1 alpha-2 code).
+pub static PHONE_DEFAULT_REGION: LazyLock<Region> =
+    LazyLock::new(|| env_or("PHONE_DEFAULT_REGION", Region::GB));
+
+/// Why a phone number was rejected, doubling as its validation error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhoneNumberError {
+    InvalidFormat,
+    NotMobile,
+}
+
+impl PhoneNumberError {
+    pub fn code(self) -> &'static str {
+        match self {
+            PhoneNumberError::InvalidFormat => "invalid_format",
+            PhoneNumberError::NotMobile => "not_mobile",
+        }
+    }
+}
+
+/// Parses `raw` with country metadata and returns its canonical E.164 form, so
+/// "+447700900123", "07700 900123" and "0044 7700 900123" are the same number.
+/// Only numbers that can be a mobile are accepted.
+pub fn normalise_phone_number(raw: &str) -> Result<String, PhoneNumberError> {
+    let number = phonenumber::parse(Some(*PHONE_DEFAULT_REGION), raw.trim())
+        .map_err(|_| PhoneNumberError::InvalidFormat)?;
+
+    if !number.is_valid() {
+        return Err(PhoneNumberError::InvalidFormat);
+    }
+
+    match number.number_type(&DATABASE) {
+        Type::Mobile | Type::FixedLineOrMobile => Ok(number.format().mode(Mode::E164).to_string()),
+        _ => Err(PhoneNumberError::NotMobile),
+    }
+}
+
+/// Rewrites phone numbers the normalisation migration could not convert (those
+/// still in national format) to E.164, since that needs country metadata. They
+/// have to pass the same checks as a number given at sign up. Rows that fail or
+/// would collide with another user are reported and flagged with
+/// `phone_number_invalid`, so later startups skip them until the user enters a
+/// new number.
+pub async fn backfill_phone_numbers(pool: &PGPool) -> Result<usize, AppError> {
+    use crate::schema::users::dsl::*;
+
+    let mut conn = pool.get().await?;
+
+    let pending: Vec<(Uuid, String)> = users
+        .filter(phone_number.not_like("+%"))
+        .filter(phone_number_invalid.eq(false))
+        .select((id, phone_number))
+        .load(&mut conn)
+        .await?;
+
+    let mut updated = 0;
+
+    for (user_id, number) in &pending {
+        let reason = match normalise_phone_number(number) {
+            Ok(canonical) => {
+                match diesel::update(users.filter(id.eq(user_id)))
+                    .set(phone_number.eq(&canonical))
+                    .execute(&mut conn)
+                    .await
+                {
+                    Ok(_) => {
+                        updated += 1;
+                        continue;
+                    }
+                    Err(DieselError::DatabaseError(DieselDbError::UniqueViolation, _)) => {
+                        "collides with another user"
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Err(e) => e.code(),
+        };
+
+        eprintln!(
+            "{:?}: WARNING: phone number of user {} could not be normalised ({}), flagging it",
+            Utc::now().timestamp() as usize,
+            user_id,
+            reason
+        );
+
+        diesel::update(users.filter(id.eq(user_id)))
+            .set(phone_number_invalid.eq(true))
+            .execute(&mut conn)
+            .await?;
+    }
+
+    Ok(updated)
+}
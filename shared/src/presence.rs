@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, OnceLock};
+
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::config::env_or;
+use super::profile::Friendship;
+use super::settings::PrivacySettings;
+
+pub type RedisConn = ConnectionManager;
+
+/// The redis connection presence is read from and written to, shared across
+/// workers. It starts out empty when redis cannot be reached at boot and is
+/// filled in once a later attempt connects, so the service runs without
+/// presence until then.
+#[derive(Clone, Default)]
+pub struct PresenceRedis(Arc<OnceLock<RedisConn>>);
+
+impl PresenceRedis {
+    /// A connection to use, or `None` while redis has not been reached yet.
+    /// Once connected the manager reconnects on its own.
+    pub fn get(&self) -> Option<RedisConn> {
+        self.0.get().cloned()
+    }
+
+    pub fn set(&self, conn: RedisConn) {
+        // only one task ever connects, so there is never a value to replace
+        let _ = self.0.set(conn);
+    }
+}
+
+/// Presence timing, configurable through `PRESENCE_TTL_SECONDS`.
+pub struct PresencePolicy {
+    /// How long a heartbeat keeps a user present. Clients should send one well
+    /// within this, after which the user shows as offline.
+    pub ttl_seconds: u64,
+}
+
+impl PresencePolicy {
+    pub fn from_env() -> Self {
+        PresencePolicy {
+            ttl_seconds: env_or("PRESENCE_TTL_SECONDS", 90),
+        }
+    }
+}
+
+pub static PRESENCE_POLICY: LazyLock<PresencePolicy> = LazyLock::new(PresencePolicy::from_env);
+
+/// The presence a user picks for themselves with each heartbeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceState {
+    Online,
+    Idle,
+    DoNotDisturb,
+    /// Connected but shown to everyone else as offline.
+    Invisible,
+}
+
+impl PresenceState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PresenceState::Online => "online",
+            PresenceState::Idle => "idle",
+            PresenceState::DoNotDisturb => "do_not_disturb",
+            PresenceState::Invisible => "invisible",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "online" => Some(PresenceState::Online),
+            "idle" => Some(PresenceState::Idle),
+            "do_not_disturb" => Some(PresenceState::DoNotDisturb),
+            "invisible" => Some(PresenceState::Invisible),
+            _ => None,
+        }
+    }
+}
+
+/// The presence other users are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    Idle,
+    DoNotDisturb,
+    Offline,
+}
+
+/// What is stored for a user: their state while heartbeats keep it alive and
+/// when they were last seen present.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StoredPresence {
+    pub state: Option<PresenceState>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Presence {
+    pub status: PresenceStatus,
+    /// Absent when the user hides it or has never been seen.
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl Presence {
+    /// `stored` as shown to a friend of its owner, whose `privacy` settings
+    /// decide whether the status and last seen time show at all. Invisible
+    /// users look offline, and their last seen time stays at the moment they
+    /// went invisible.
+    pub fn for_friend(stored: StoredPresence, privacy: &PrivacySettings) -> Self {
+        let status = match stored.state {
+            _ if !Friendship::Friends.can_see(privacy.presence_visibility) => {
+                PresenceStatus::Offline
+            }
+            Some(PresenceState::Online) => PresenceStatus::Online,
+            Some(PresenceState::Idle) => PresenceStatus::Idle,
+            Some(PresenceState::DoNotDisturb) => PresenceStatus::DoNotDisturb,
+            Some(PresenceState::Invisible) | None => PresenceStatus::Offline,
+        };
+
+        Presence {
+            status,
+            last_seen: stored
+                .last_seen
+                .filter(|_| Friendship::Friends.can_see(privacy.last_seen_visibility)),
+        }
+    }
+}
+
+fn state_key(user: Uuid) -> String {
+    format!("presence:{}", user)
+}
+
+fn last_seen_key(user: Uuid) -> String {
+    format!("presence:last_seen:{}", user)
+}
+
+/// Records a heartbeat from `user`. The state lapses after the presence TTL
+/// unless another heartbeat refreshes it, and everything but invisible moves
+/// the last seen time forward.
+pub async fn set_presence(
+    conn: &mut RedisConn,
+    user: Uuid,
+    state: PresenceState,
+) -> RedisResult<()> {
+    let mut pipe = redis::pipe();
+
+    pipe.set_ex(state_key(user), state.as_str(), PRESENCE_POLICY.ttl_seconds)
+        .ignore();
+
+    if state != PresenceState::Invisible {
+        pipe.set(last_seen_key(user), Utc::now().timestamp())
+            .ignore();
+    }
+
+    pipe.query_async(conn).await
+}
+
+/// Marks `user` offline straight away instead of waiting for the TTL, as on
+/// sign out.
+pub async fn clear_presence(conn: &mut RedisConn, user: Uuid) -> RedisResult<()> {
+    let previous: Option<String> = conn.get_del(state_key(user)).await?;
+
+    match previous.as_deref().and_then(PresenceState::parse) {
+        Some(PresenceState::Invisible) | None => Ok(()),
+        Some(_) => conn.set(last_seen_key(user), Utc::now().timestamp()).await,
+    }
+}
+
+/// The stored presence of each of `users` in one round trip. Users with
+/// nothing stored are left out.
+pub async fn load_presence(
+    conn: &mut RedisConn,
+    users: &[Uuid],
+) -> RedisResult<HashMap<Uuid, StoredPresence>> {
+    if users.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let state_keys: Vec<String> = users.iter().map(|user| state_key(*user)).collect();
+    let last_seen_keys: Vec<String> = users.iter().map(|user| last_seen_key(*user)).collect();
+
+    let (states, last_seens): (Vec<Option<String>>, Vec<Option<i64>>) = redis::pipe()
+        .mget(state_keys)
+        .mget(last_seen_keys)
+        .query_async(conn)
+        .await?;
+
+    Ok(users
+        .iter()
+        .zip(states.into_iter().zip(last_seens))
+        .filter_map(|(user, (state, last_seen))| {
+            let stored = StoredPresence {
+                state: state.as_deref().and_then(PresenceState::parse),
+                last_seen: last_seen.and_then(|secs| DateTime::from_timestamp(secs, 0)),
+            };
+
+            (stored.state.is_some() || stored.last_seen.is_some()).then_some((*user, stored))
+        })
+        .collect())
+}
@@ -1,69 +1,435 @@
+use std::collections::BTreeMap;
+
+use actix_web::http::header::EntityTag;
 use actix_web::web;
-use chrono::Utc;
-use diesel::{ExpressionMethods, query_dsl::methods::FilterDsl};
-use diesel_async::RunQueryDsl;
+use chrono::{DateTime, Utc};
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use serde::Serialize;
 use uuid::Uuid;
 
+use super::block::is_blocked_between;
 use super::database::PGPool;
-use super::models::{UpdateUser, User};
-use diesel::result::DatabaseErrorKind as DieselDbError;
-use diesel::result::Error as DieselError;
+use super::error::AppError;
+use super::friend_request::FRIEND_REQUEST_POLICY;
+use super::friendship::are_friends;
+use super::media::media_url;
+use super::models::{CreateUsernameHistory, FriendRequestStatus, PublicUserRow, UpdateUser, User};
+use super::settings::{PrivacySettings, Visibility, load_settings};
+use super::username::{USERNAME_POLICY, fold_username};
+use super::validate::{ValidationErrors, unique_violation_to_validation};
 
-pub async fn get_user_by_id(pool: web::Data<PGPool>, user_id: &str) -> Result<User, DieselError> {
+pub async fn get_user_by_id(pool: web::Data<PGPool>, user_id: &str) -> Result<User, AppError> {
     use crate::models::User;
     use crate::schema::users::dsl::*;
 
-    let mut conn = pool.get().await.map_err(|e| {
-        eprintln!(
-            "{:?}: Failed to acquire DB connection: {:?}",
-            Utc::now().timestamp() as usize,
-            e
-        );
-        DieselError::DatabaseError(DieselDbError::UnableToSendCommand, Box::new(e.to_string()))
-    })?;
-
-    let parsed_uuid = Uuid::parse_str(user_id).map_err(|e| {
-        eprintln!(
-            "{:?}: Failed to acquire DB connection: {:?}",
-            Utc::now().timestamp() as usize,
-            e
-        );
-        DieselError::DatabaseError(DieselDbError::UnableToSendCommand, Box::new(e.to_string()))
-    })?;
+    let mut conn = pool.get().await?;
 
-    let user_result = users
+    let parsed_uuid = Uuid::parse_str(user_id).map_err(|_| AppError::invalid_uuid("user_id"))?;
+
+    users
         .filter(id.eq(parsed_uuid))
-        .first::<User>(&mut conn)
-        .await;
+        .select(User::as_select())
+        .first(&mut conn)
+        .await
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => {
+                AppError::not_found("user_not_found", "user not found")
+            }
+            e => e.into(),
+        })
+}
+
+/// Strong ETag of a user's own profile, derived from `users.updated_at`. An
+/// expired custom status is hidden without touching the row, so whether it has
+/// expired goes into the tag as well.
+pub fn profile_etag(
+    updated_at: DateTime<Utc>,
+    status_expires_at: Option<DateTime<Utc>>,
+) -> EntityTag {
+    let version = updated_at.timestamp_micros();
 
-    match user_result {
-        Ok(user) => Ok(user),
-        Err(_) => Err(DieselError::NotFound),
+    if status_expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        EntityTag::new_strong(format!("{}-status-expired", version))
+    } else {
+        EntityTag::new_strong(version.to_string())
     }
 }
 
+/// Saves profile changes and returns the new ETag. When `if_match` is
+/// given, the update only goes ahead if the profile still matches one of those
+/// ETags, so concurrent edits from two devices cannot overwrite each other. A
+/// change of username (beyond re-casing) is recorded in `username_history` in
+/// the same transaction, and refused while the user is still within the rename
+/// cooldown.
 pub async fn apply_profile_update(
     pool: web::Data<PGPool>,
     user_uuid: Uuid,
     changes: UpdateUser,
-) -> Result<bool, DieselError> {
-    use crate::schema::users::dsl::users;
-    use crate::schema::users::*;
-
-    let mut conn = pool.get().await.map_err(|e| {
-        eprintln!(
-            "{:?}: Failed to acquire DB connection: {:?}",
-            Utc::now().timestamp(),
-            e
+    if_match: Option<&[EntityTag]>,
+) -> Result<EntityTag, AppError> {
+    let mut conn = pool.get().await?;
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        async move {
+            use crate::schema::users::dsl::*;
+
+            let (old_username, old_normalised, current_version, current_expiry): (
+                String,
+                String,
+                DateTime<Utc>,
+                Option<DateTime<Utc>>,
+            ) = users
+                .filter(id.eq(user_uuid))
+                .select((username, username_normalised, updated_at, status_expires_at))
+                .for_update()
+                .first(conn)
+                .await?;
+
+            let current = profile_etag(current_version, current_expiry);
+
+            if if_match.is_some_and(|tags| !tags.iter().any(|tag| tag.strong_eq(&current))) {
+                return Err(AppError::precondition_failed(
+                    "profile_modified",
+                    "profile was modified by another request",
+                ));
+            }
+
+            let renamed = changes
+                .username_normalised
+                .as_deref()
+                .is_some_and(|new_normalised| new_normalised != old_normalised);
+
+            if renamed {
+                record_username_change(conn, user_uuid, old_username, old_normalised).await?;
+            }
+
+            let result = diesel::update(users)
+                .set(&changes)
+                .filter(id.eq(user_uuid))
+                .returning((updated_at, status_expires_at))
+                .get_result(conn)
+                .await;
+
+            match result {
+                Ok((new_version, new_expiry)) => Ok(profile_etag(new_version, new_expiry)),
+                // nothing to change, e.g. an empty request body
+                Err(DieselError::QueryBuilderError(_)) => Ok(current),
+                Err(e) => Err(unique_violation_to_validation(e)),
+            }
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Moves the current username of `user` into its history, unless the user
+/// renamed within `USERNAME_POLICY.change_cooldown`.
+async fn record_username_change(
+    conn: &mut AsyncPgConnection,
+    user: Uuid,
+    old_username: String,
+    old_normalised: String,
+) -> Result<(), AppError> {
+    use crate::schema::username_history::dsl::*;
+
+    let last_change: Option<DateTime<Utc>> = username_history
+        .filter(user_id.eq(user))
+        .select(diesel::dsl::max(changed_at))
+        .first(conn)
+        .await?;
+
+    let next_allowed = last_change
+        .map(|last| last + USERNAME_POLICY.change_cooldown)
+        .filter(|available_at| *available_at > Utc::now());
+
+    if let Some(available_at) = next_allowed {
+        let mut errors = ValidationErrors::default();
+        errors.add_with_params(
+            "username",
+            "cooldown",
+            BTreeMap::from([("available_at", available_at.to_rfc3339().into())]),
         );
-        DieselError::DatabaseError(DieselDbError::UnableToSendCommand, Box::new(e.to_string()))
-    })?;
+        return errors.into_result();
+    }
 
-    diesel::update(users)
-        .set(&changes)
-        .filter(id.eq(user_uuid))
-        .execute(&mut conn)
+    diesel::insert_into(username_history)
+        .values(CreateUsernameHistory {
+            user_id: user,
+            username: old_username,
+            username_normalised: old_normalised,
+        })
+        .execute(conn)
         .await?;
 
-    Ok(true)
+    Ok(())
+}
+
+/// How the viewer of a profile is related to its owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Friendship {
+    #[serde(rename = "self")]
+    Myself,
+    Friends,
+    RequestSent,
+    RequestReceived,
+    None,
+}
+
+impl Friendship {
+    /// Whether a field with the given visibility may be shown to this viewer.
+    pub fn can_see(self, visibility: Visibility) -> bool {
+        match visibility {
+            Visibility::Everyone => true,
+            Visibility::Friends => matches!(self, Friendship::Myself | Friendship::Friends),
+            Visibility::Nobody => self == Friendship::Myself,
+        }
+    }
+}
+
+/// A user's custom status. Either part may be missing, and it disappears on its
+/// own at `expires_at` when that is set.
+#[derive(Serialize)]
+pub struct CustomStatus {
+    pub text: Option<String>,
+    pub emoji: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl CustomStatus {
+    /// The status of `user`, unless none is set or it has expired.
+    pub fn of(user: &User) -> Option<Self> {
+        Self::new(
+            user.status_text.clone(),
+            user.status_emoji.clone(),
+            user.status_expires_at,
+        )
+    }
+
+    /// A status from its stored columns, unless none is set or it has expired.
+    pub fn new(
+        text: Option<String>,
+        emoji: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Option<Self> {
+        let expired = expires_at.is_some_and(|expires_at| expires_at <= Utc::now());
+
+        if (text.is_none() && emoji.is_none()) || expired {
+            return None;
+        }
+
+        Some(CustomStatus {
+            text,
+            emoji,
+            expires_at,
+        })
+    }
+}
+
+/// The few fields of a user that are safe to show anyone who can see them at
+/// all, used wherever other users appear in a list.
+#[derive(Serialize)]
+pub struct PublicUser {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub pronouns: Option<String>,
+    pub profile_pic: Option<String>,
+    pub status: Option<CustomStatus>,
+    pub timezone: Option<String>,
+}
+
+impl From<PublicUserRow> for PublicUser {
+    fn from(user: PublicUserRow) -> Self {
+        PublicUser {
+            status: CustomStatus::new(user.status_text, user.status_emoji, user.status_expires_at),
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+            pronouns: user.pronouns,
+            profile_pic: user.profile_pic_id.map(media_url),
+            timezone: user.timezone,
+        }
+    }
+}
+
+/// What other users may see of a profile. Email and phone number are only
+/// included when the owner's privacy settings allow it for this viewer.
+#[derive(Serialize)]
+pub struct PublicProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub pronouns: Option<String>,
+    pub profile_pic: Option<String>,
+    pub bio: Option<String>,
+    pub status: Option<CustomStatus>,
+    pub timezone: Option<String>,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phone_number: Option<String>,
+    pub friendship: Friendship,
+}
+
+impl PublicProfile {
+    pub fn new(user: User, privacy: &PrivacySettings, friendship: Friendship) -> Self {
+        let status = CustomStatus::of(&user);
+
+        PublicProfile {
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+            pronouns: user.pronouns,
+            profile_pic: user.profile_pic_id.map(media_url),
+            bio: user.bio,
+            status,
+            timezone: user.timezone,
+            created_at: user.created_at,
+            email: friendship
+                .can_see(privacy.email_visibility)
+                .then_some(user.email),
+            phone_number: friendship
+                .can_see(privacy.phone_number_visibility)
+                .then_some(user.phone_number),
+            friendship,
+        }
+    }
+}
+
+/// A profile is looked up either by its id or by username.
+pub enum ProfileLookup<'a> {
+    Id(Uuid),
+    Username(&'a str),
+}
+
+pub async fn get_public_profile(
+    pool: web::Data<PGPool>,
+    viewer: Uuid,
+    lookup: ProfileLookup<'_>,
+) -> Result<PublicProfile, AppError> {
+    use crate::schema::users::dsl::*;
+
+    let mut conn = pool.get().await?;
+
+    let query = users
+        .filter(deleted_at.is_null())
+        .select(User::as_select())
+        .into_boxed();
+
+    let query = match lookup {
+        ProfileLookup::Id(user_id) => query.filter(id.eq(user_id)),
+        ProfileLookup::Username(name) => {
+            let user_id = resolve_username(&mut conn, name)
+                .await?
+                .ok_or_else(|| AppError::not_found("user_not_found", "user not found"))?;
+
+            query.filter(id.eq(user_id))
+        }
+    };
+
+    let user = query
+        .first(&mut conn)
+        .await
+        .optional()?
+        .ok_or_else(|| AppError::not_found("user_not_found", "user not found"))?;
+
+    // blocked users cannot see each other at all
+    if is_blocked_between(&mut conn, viewer, user.id).await? {
+        return Err(AppError::not_found("user_not_found", "user not found"));
+    }
+
+    let friendship = get_friendship(&mut conn, viewer, user.id).await?;
+    let settings = load_settings(&mut conn, user.id).await?;
+
+    Ok(PublicProfile::new(user, &settings.privacy, friendship))
+}
+
+/// Finds the user currently called `name` or, failing that, the user who gave
+/// up `name` most recently within the hold period, so old links and requests
+/// sent by name keep reaching a renamed user.
+pub async fn resolve_username(
+    conn: &mut AsyncPgConnection,
+    name: &str,
+) -> QueryResult<Option<Uuid>> {
+    use crate::schema::username_history::dsl as h;
+    use crate::schema::users::dsl as u;
+
+    let folded = fold_username(name);
+
+    let current = u::users
+        .filter(u::username_normalised.eq(&folded))
+        .filter(u::deleted_at.is_null())
+        .select(u::id)
+        .first(conn)
+        .await
+        .optional()?;
+
+    if current.is_some() {
+        return Ok(current);
+    }
+
+    h::username_history
+        .inner_join(u::users)
+        .filter(h::username_normalised.eq(&folded))
+        .filter(h::changed_at.gt(Utc::now() - USERNAME_POLICY.hold_period))
+        .filter(u::deleted_at.is_null())
+        .order(h::changed_at.desc())
+        .select(h::user_id)
+        .first(conn)
+        .await
+        .optional()
+}
+
+/// Works out the relation between two users, looking at pending requests only
+/// when they are not friends.
+pub async fn get_friendship(
+    conn: &mut AsyncPgConnection,
+    viewer: Uuid,
+    other: Uuid,
+) -> QueryResult<Friendship> {
+    use crate::schema::friend_request::dsl as fr;
+
+    if viewer == other {
+        return Ok(Friendship::Myself);
+    }
+
+    if are_friends(conn, viewer, other).await? {
+        return Ok(Friendship::Friends);
+    }
+
+    // expired requests count as gone before the sweep gets to them
+    let expired_before = Utc::now() - FRIEND_REQUEST_POLICY.expiry;
+
+    let (request_sent, request_received) = diesel::select((
+        exists(
+            fr::friend_request
+                .filter(fr::requester.eq(viewer))
+                .filter(fr::receiver.eq(other))
+                .filter(fr::status.eq(FriendRequestStatus::Pending))
+                .filter(fr::created_at.ge(expired_before)),
+        ),
+        exists(
+            fr::friend_request
+                .filter(fr::requester.eq(other))
+                .filter(fr::receiver.eq(viewer))
+                .filter(fr::status.eq(FriendRequestStatus::Pending))
+                .filter(fr::created_at.ge(expired_before)),
+        ),
+    ))
+    .get_result::<(bool, bool)>(conn)
+    .await?;
+
+    Ok(if request_sent {
+        Friendship::RequestSent
+    } else if request_received {
+        Friendship::RequestReceived
+    } else {
+        Friendship::None
+    })
 }
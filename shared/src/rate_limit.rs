@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use super::error::AppError;
+
+/// Fixed-window limiter allowing each user `max_requests` per `window`. State
+/// is kept in memory, so the limit applies per service instance.
+pub struct RateLimiter {
+    max_requests: u32,
+    window: Duration,
+    windows: Mutex<HashMap<Uuid, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: u32, window: Duration) -> Self {
+        RateLimiter {
+            max_requests,
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a request by `user`, failing with `rate_limited` once the user has
+    /// used up the current window.
+    pub fn check(&self, user: Uuid) -> Result<(), AppError> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+
+        // forget users whose window has passed so the map does not grow forever
+        if windows.len() > 10_000 {
+            windows.retain(|_, (started, _)| now.duration_since(*started) < self.window);
+        }
+
+        let (started, count) = windows.entry(user).or_insert((now, 0));
+
+        if now.duration_since(*started) >= self.window {
+            *started = now;
+            *count = 0;
+        }
+
+        if *count >= self.max_requests {
+            let retry_after = self.window.saturating_sub(now.duration_since(*started));
+
+            return Err(AppError::too_many_requests(
+                "rate_limited",
+                "too many requests",
+                retry_after.as_secs().max(1),
+            ));
+        }
+
+        *count += 1;
+
+        Ok(())
+    }
+}
@@ -1,5 +1,13 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    dismissed_suggestion (user_id, dismissed) {
+        user_id -> Uuid,
+        dismissed -> Uuid,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     friend (user1, user2) {
         user1 -> Uuid,
@@ -8,11 +16,48 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    friend_list (id) {
+        id -> Uuid,
+        owner_id -> Uuid,
+        name -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    friend_list_member (list_id, friend_id) {
+        list_id -> Uuid,
+        friend_id -> Uuid,
+        added_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    friend_meta (user_id, friend_id) {
+        user_id -> Uuid,
+        friend_id -> Uuid,
+        favourite -> Bool,
+        nickname -> Nullable<Text>,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     friend_request (requester, receiver) {
         requester -> Uuid,
         receiver -> Uuid,
         created_at -> Timestamptz,
+        status -> Text,
+        responded_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    friend_request_send (id) {
+        id -> Uuid,
+        requester -> Uuid,
+        sent_at -> Timestamptz,
     }
 }
 
@@ -36,6 +81,57 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    media (id) {
+        id -> Uuid,
+        owner_id -> Uuid,
+        content_hash -> Text,
+        content_type -> Text,
+        byte_size -> Int8,
+        created_at -> Timestamptz,
+        kind -> Text,
+        width -> Nullable<Int4>,
+        height -> Nullable<Int4>,
+        variant_of -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    reserved_username_grant (username_normalised) {
+        username_normalised -> Text,
+        user_id -> Uuid,
+        granted_by -> Uuid,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_block (blocker, blocked) {
+        blocker -> Uuid,
+        blocked -> Uuid,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_settings (user_id) {
+        user_id -> Uuid,
+        version -> Int4,
+        settings -> Jsonb,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    username_history (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        username -> Text,
+        username_normalised -> Text,
+        changed_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Uuid,
@@ -44,14 +140,53 @@ diesel::table! {
         phone_number -> Text,
         two_factor_auth -> Bool,
         password_hash -> Text,
-        profile_pic -> Nullable<Text>,
+        legacy_profile_pic -> Nullable<Text>,
         bio -> Nullable<Text>,
         created_at -> Timestamptz,
+        username_normalised -> Text,
+        username_skeleton -> Nullable<Text>,
+        is_admin -> Bool,
+        profile_pic_id -> Nullable<Uuid>,
+        email_verified -> Bool,
+        phone_number_verified -> Bool,
+        phone_number_invalid -> Bool,
+        deleted_at -> Nullable<Timestamptz>,
+        display_name -> Nullable<Text>,
+        pronouns -> Nullable<Text>,
+        status_text -> Nullable<Text>,
+        status_emoji -> Nullable<Text>,
+        status_expires_at -> Nullable<Timestamptz>,
+        timezone -> Nullable<Text>,
+        updated_at -> Timestamptz,
+        legacy_profile_pic_invalid -> Bool,
     }
 }
 
+diesel::joinable!(friend_list -> users (owner_id));
+diesel::joinable!(friend_list_member -> friend_list (list_id));
+diesel::joinable!(friend_list_member -> users (friend_id));
+diesel::joinable!(friend_request_send -> users (requester));
 diesel::joinable!(group_members -> groups (group_id));
 diesel::joinable!(group_members -> users (user_id));
 diesel::joinable!(groups -> users (created_by));
+diesel::joinable!(media -> users (owner_id));
+diesel::joinable!(user_settings -> users (user_id));
+diesel::joinable!(username_history -> users (user_id));
 
-diesel::allow_tables_to_appear_in_same_query!(friend, friend_request, group_members, groups, users,);
+diesel::allow_tables_to_appear_in_same_query!(
+    dismissed_suggestion,
+    friend,
+    friend_list,
+    friend_list_member,
+    friend_meta,
+    friend_request,
+    friend_request_send,
+    group_members,
+    groups,
+    media,
+    reserved_username_grant,
+    user_block,
+    user_settings,
+    username_history,
+    users,
+);
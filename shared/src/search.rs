@@ -0,0 +1,182 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use actix_web::web;
+use diesel::dsl::{exists, not};
+use diesel::expression::BoxableExpression;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Float4, Text};
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::config::env_or;
+use super::database::PGPool;
+use super::error::AppError;
+use super::media::media_url;
+use super::phone::normalise_phone_number;
+use super::rate_limit::RateLimiter;
+use super::username::fold_username;
+use super::validate::ValidationErrors;
+
+pub const SEARCH_MAX_QUERY_LEN: usize = 64;
+pub const SEARCH_DEFAULT_PAGE_SIZE: u32 = 20;
+pub const SEARCH_MAX_PAGE_SIZE: u32 = 50;
+
+/// Searches allowed per user per minute, configurable through `SEARCH_RATE_LIMIT`.
+pub static SEARCH_RATE_LIMITER: LazyLock<RateLimiter> =
+    LazyLock::new(|| RateLimiter::new(env_or("SEARCH_RATE_LIMIT", 30), Duration::from_secs(60)));
+
+diesel::infix_operator!(TrigramSimilar, " % ", backend: Pg);
+
+define_sql_function!(fn similarity(a: Text, b: Text) -> Float4);
+define_sql_function!(fn lower(a: Text) -> Text);
+define_sql_function!(fn greatest(a: Float4, b: Float4) -> Float4);
+
+#[derive(Serialize)]
+pub struct SearchResult {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub profile_pic: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    pub page: u32,
+    pub per_page: u32,
+    pub has_more: bool,
+}
+
+/// Checks the search term and paging parameters, returning the trimmed term.
+pub fn validate_search_params<'a>(
+    term: &'a str,
+    page: u32,
+    per_page: u32,
+    errors: &mut ValidationErrors,
+) -> &'a str {
+    let term = term.trim();
+    let length = term.chars().count();
+
+    if length == 0 || length > SEARCH_MAX_QUERY_LEN {
+        errors.add_length("q", 1, SEARCH_MAX_QUERY_LEN);
+    }
+
+    if page == 0 {
+        errors.add("page", "invalid_value");
+    }
+
+    if per_page == 0 || per_page > SEARCH_MAX_PAGE_SIZE {
+        errors.add_length("per_page", 1, SEARCH_MAX_PAGE_SIZE as usize);
+    }
+
+    term
+}
+
+/// Escapes `%`, `_` and `\` so `s` is matched literally by `LIKE`.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Finds users whose username or display name starts with or is similar (by
+/// trigram) to `term`, plus the user whose verified email or phone number is
+/// exactly `term`, so contacts can be discovered without guessing usernames.
+/// Exact username matches rank first, then prefix matches, then the closest
+/// fuzzy matches. The viewer, deleted accounts and users blocked by or blocking
+/// the viewer are never returned.
+pub async fn search_users(
+    pool: web::Data<PGPool>,
+    viewer: Uuid,
+    term: &str,
+    page: u32,
+    per_page: u32,
+) -> Result<SearchPage, AppError> {
+    use crate::schema::user_block::dsl as b;
+    use crate::schema::users::dsl::*;
+
+    let folded = fold_username(term);
+    let prefix = format!("{}%", escape_like(&folded));
+
+    let lowered = term.to_lowercase();
+    let display_prefix = format!("{}%", escape_like(&lowered));
+
+    // rows without a display name compare as NULL and never match on it
+    let display = || lower(display_name.assume_not_null());
+
+    let mut matches: Box<dyn BoxableExpression<users, Pg, SqlType = Bool>> = Box::new(
+        username_normalised
+            .like(prefix.clone())
+            .or(TrigramSimilar::new(
+                username_normalised,
+                folded.clone().into_sql::<Text>(),
+            ))
+            .or(display().like(display_prefix.clone()))
+            .or(TrigramSimilar::new(
+                display(),
+                lowered.clone().into_sql::<Text>(),
+            )),
+    );
+
+    if term.contains('@') {
+        matches = Box::new(matches.or(email_verified.and(lower(email).eq(lowered.clone()))));
+    }
+
+    if let Ok(e164) = normalise_phone_number(term) {
+        matches = Box::new(matches.or(phone_number_verified.and(phone_number.eq(e164))));
+    }
+
+    let mut conn = pool.get().await?;
+
+    let mut rows: Vec<(Uuid, String, Option<String>, Option<Uuid>)> = users
+        .filter(deleted_at.is_null())
+        .filter(id.ne(viewer))
+        .filter(not(exists(
+            b::user_block.filter(
+                b::blocker
+                    .eq(viewer)
+                    .and(b::blocked.eq(id))
+                    .or(b::blocker.eq(id).and(b::blocked.eq(viewer))),
+            ),
+        )))
+        .filter(matches)
+        .order((
+            username_normalised.eq(folded.clone()).desc(),
+            username_normalised
+                .like(prefix)
+                .or(display().like(display_prefix))
+                .desc(),
+            greatest(
+                similarity(username_normalised, folded),
+                similarity(display(), lowered),
+            )
+            .desc(),
+            username_normalised.asc(),
+        ))
+        .select((id, username, display_name, profile_pic_id))
+        .offset(i64::from(page - 1) * i64::from(per_page))
+        .limit(i64::from(per_page) + 1)
+        .load(&mut conn)
+        .await?;
+
+    let has_more = rows.len() > per_page as usize;
+    rows.truncate(per_page as usize);
+
+    Ok(SearchPage {
+        results: rows
+            .into_iter()
+            .map(|(user_id, name, display, pic)| SearchResult {
+                id: user_id,
+                username: name,
+                display_name: display,
+                profile_pic: pic.map(media_url),
+            })
+            .collect(),
+        page,
+        per_page,
+        has_more,
+    })
+}
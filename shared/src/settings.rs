@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use actix_web::web;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use super::database::PGPool;
+use super::error::AppError;
+use super::validate::ValidationErrors;
+
+/// Version of the settings document written by this build. Stored documents
+/// with an older version are upgraded by `upgrade_settings` when read.
+pub const SETTINGS_VERSION: i32 = 1;
+
+static LOCALE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-z]{2,3}(-[A-Z][a-z]{3})?(-([A-Z]{2}|[0-9]{3}))?$").unwrap());
+
+/// Per-user preferences synced between clients. Every field has a default, so
+/// stored documents only need the keys a user has changed and fields added in
+/// later versions fill themselves in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserSettings {
+    pub theme: Theme,
+    pub locale: String,
+    pub notifications: NotificationSettings,
+    pub privacy: PrivacySettings,
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        UserSettings {
+            theme: Theme::default(),
+            locale: "en-GB".to_string(),
+            notifications: NotificationSettings::default(),
+            privacy: PrivacySettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationSettings {
+    pub friend_requests: bool,
+    pub friend_accepted: bool,
+    pub messages: bool,
+    pub mentions: bool,
+    pub sounds: bool,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        NotificationSettings {
+            friend_requests: true,
+            friend_accepted: true,
+            messages: true,
+            mentions: true,
+            sounds: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivacySettings {
+    pub email_visibility: Visibility,
+    pub phone_number_visibility: Visibility,
+    pub friend_requests_from: FriendRequestsFrom,
+    /// Whether friends see the user online, idle or busy.
+    pub presence_visibility: Visibility,
+    /// Whether friends see when the user was last online.
+    pub last_seen_visibility: Visibility,
+}
+
+impl Default for PrivacySettings {
+    fn default() -> Self {
+        PrivacySettings {
+            email_visibility: Visibility::default(),
+            phone_number_visibility: Visibility::default(),
+            friend_requests_from: FriendRequestsFrom::default(),
+            presence_visibility: Visibility::Friends,
+            last_seen_visibility: Visibility::Friends,
+        }
+    }
+}
+
+/// Who may see a field on a user's public profile.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    Everyone,
+    Friends,
+    #[default]
+    Nobody,
+}
+
+/// Who may send a user friend requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FriendRequestsFrom {
+    #[default]
+    Everyone,
+    FriendsOfFriends,
+    Nobody,
+}
+
+/// Settings as returned to clients, tagged with the schema version.
+#[derive(Serialize)]
+pub struct VersionedSettings {
+    pub version: i32,
+    #[serde(flatten)]
+    pub settings: UserSettings,
+}
+
+/// Applies an RFC 7396 JSON merge patch: objects are merged recursively, `null`
+/// removes a key (resetting a setting to its default) and anything else
+/// replaces the target.
+pub fn merge_patch(target: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(&key);
+            } else {
+                merge_patch(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// Brings a stored document up to `SETTINGS_VERSION`. Version 1 is the first,
+/// so there is nothing to upgrade yet; each later version adds a step here that
+/// renames or reshapes keys before defaults are applied.
+fn upgrade_settings(version: i32, document: Value) -> Value {
+    debug_assert!(version <= SETTINGS_VERSION);
+    document
+}
+
+pub fn validate_settings(settings: &UserSettings, errors: &mut ValidationErrors) {
+    if !LOCALE_REGEX.is_match(&settings.locale) {
+        errors.add("locale", "invalid_locale");
+    }
+}
+
+/// Loads the settings document of `user`, upgraded to the current version.
+pub async fn load_settings_document(
+    conn: &mut AsyncPgConnection,
+    user: Uuid,
+) -> QueryResult<Value> {
+    use crate::schema::user_settings::dsl::*;
+
+    let stored: Option<(i32, Value)> = user_settings
+        .filter(user_id.eq(user))
+        .select((version, settings))
+        .first(conn)
+        .await
+        .optional()?;
+
+    Ok(match stored {
+        Some((stored_version, document)) => upgrade_settings(stored_version, document),
+        None => Value::Object(Map::new()),
+    })
+}
+
+/// Reads an upgraded settings document, falling back to the defaults if it no
+/// longer parses.
+fn parse_settings(user: Uuid, document: Value) -> UserSettings {
+    serde_json::from_value(document).unwrap_or_else(|e| {
+        eprintln!(
+            "{:?}: WARNING: settings of user {} are invalid, using the defaults: {}",
+            Utc::now().timestamp() as usize,
+            user,
+            e
+        );
+        UserSettings::default()
+    })
+}
+
+/// The settings of `user`, with defaults for anything never set.
+pub async fn load_settings(conn: &mut AsyncPgConnection, user: Uuid) -> QueryResult<UserSettings> {
+    let document = load_settings_document(conn, user).await?;
+
+    Ok(parse_settings(user, document))
+}
+
+/// The settings of each of `users` in one query. Users who never saved any
+/// are left out; use `UserSettings::default()` for them.
+pub async fn load_settings_many(
+    conn: &mut AsyncPgConnection,
+    users: &[Uuid],
+) -> QueryResult<HashMap<Uuid, UserSettings>> {
+    use crate::schema::user_settings::dsl::*;
+
+    let stored: Vec<(Uuid, i32, Value)> = user_settings
+        .filter(user_id.eq_any(users))
+        .select((user_id, version, settings))
+        .load(conn)
+        .await?;
+
+    Ok(stored
+        .into_iter()
+        .map(|(user, stored_version, document)| {
+            (
+                user,
+                parse_settings(user, upgrade_settings(stored_version, document)),
+            )
+        })
+        .collect())
+}
+
+pub async fn get_settings(
+    pool: web::Data<PGPool>,
+    user: Uuid,
+) -> Result<VersionedSettings, AppError> {
+    let mut conn = pool.get().await?;
+
+    Ok(VersionedSettings {
+        version: SETTINGS_VERSION,
+        settings: load_settings(&mut conn, user).await?,
+    })
+}
+
+/// Merges `patch` into the stored document of `user` and saves it if the
+/// result is still a valid settings document. The row is locked for the
+/// read-modify-write, so patches from two devices at once both apply.
+pub async fn patch_settings(
+    pool: web::Data<PGPool>,
+    user: Uuid,
+    patch: Value,
+) -> Result<VersionedSettings, AppError> {
+    use crate::schema::user_settings::dsl::*;
+
+    if !patch.is_object() {
+        return Err(AppError::bad_request(
+            "invalid_settings",
+            "settings patch must be a JSON object",
+        ));
+    }
+
+    let mut conn = pool.get().await?;
+
+    let patched = conn
+        .transaction::<_, AppError, _>(|conn| {
+            async move {
+                // make sure there is a row to lock for users who never saved
+                diesel::insert_into(user_settings)
+                    .values((
+                        user_id.eq(user),
+                        version.eq(SETTINGS_VERSION),
+                        settings.eq(Value::Object(Map::new())),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .await?;
+
+                let (stored_version, stored): (i32, Value) = user_settings
+                    .filter(user_id.eq(user))
+                    .select((version, settings))
+                    .for_update()
+                    .first(conn)
+                    .await?;
+
+                let mut document = upgrade_settings(stored_version, stored);
+                merge_patch(&mut document, patch);
+
+                // keys the document lacks take their default, so it only ever
+                // holds what the user changed
+                let patched: UserSettings = serde_json::from_value(document.clone())
+                    .map_err(|e| AppError::bad_request("invalid_settings", e.to_string()))?;
+
+                let mut errors = ValidationErrors::default();
+                validate_settings(&patched, &mut errors);
+                errors.into_result()?;
+
+                diesel::update(user_settings.filter(user_id.eq(user)))
+                    .set((
+                        version.eq(SETTINGS_VERSION),
+                        settings.eq(&document),
+                        updated_at.eq(Utc::now()),
+                    ))
+                    .execute(conn)
+                    .await?;
+
+                Ok(patched)
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    Ok(VersionedSettings {
+        version: SETTINGS_VERSION,
+        settings: patched,
+    })
+}
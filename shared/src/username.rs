@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+use std::fs;
+use std::sync::LazyLock;
+
+use caseless::default_case_fold_str;
+use chrono::{TimeDelta, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::confusable_detection::skeleton;
+use uuid::Uuid;
+
+use super::config::env_or;
+use super::database::PGPool;
+use super::error::AppError;
+
+const DEFAULT_ALLOWED_PATTERN: &str = r"^[\p{L}\p{Nd}]+$";
+const DEFAULT_RESERVED_USERNAMES: &str = include_str!("../data/reserved_usernames.txt");
+const DEFAULT_BLOCKED_TERMS: &str = include_str!("../data/blocked_username_terms.txt");
+
+/// Username rules, configurable through `USERNAME_MIN_LENGTH`,
+/// `USERNAME_MAX_LENGTH`, `USERNAME_ALLOWED_PATTERN`,
+/// `USERNAME_CHANGE_COOLDOWN_DAYS` and `USERNAME_HOLD_DAYS`. Lengths are counted
+/// in characters of the NFKC-normalised name, not bytes.
+pub struct UsernamePolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub allowed_pattern: Regex,
+    /// Minimum time between two renames of the same user.
+    pub change_cooldown: TimeDelta,
+    /// How long a former username stays reserved for its previous owner and
+    /// keeps resolving to them.
+    pub hold_period: TimeDelta,
+}
+
+impl UsernamePolicy {
+    pub fn from_env() -> Self {
+        let pattern = env_or(
+            "USERNAME_ALLOWED_PATTERN",
+            DEFAULT_ALLOWED_PATTERN.to_string(),
+        );
+
+        let allowed_pattern = Regex::new(&pattern).unwrap_or_else(|e| {
+            eprintln!("WARNING: invalid USERNAME_ALLOWED_PATTERN, using the default: {e}");
+            Regex::new(DEFAULT_ALLOWED_PATTERN).unwrap()
+        });
+
+        UsernamePolicy {
+            min_length: env_or("USERNAME_MIN_LENGTH", 8),
+            max_length: env_or("USERNAME_MAX_LENGTH", 16),
+            allowed_pattern,
+            change_cooldown: TimeDelta::days(env_or("USERNAME_CHANGE_COOLDOWN_DAYS", 30)),
+            hold_period: TimeDelta::days(env_or("USERNAME_HOLD_DAYS", 90)),
+        }
+    }
+}
+
+pub static USERNAME_POLICY: LazyLock<UsernamePolicy> = LazyLock::new(UsernamePolicy::from_env);
+
+/// Reserved names and blocked terms, loaded from `RESERVED_USERNAMES_FILE` and
+/// `BLOCKED_USERNAME_TERMS_FILE` or the defaults bundled in `shared/data`. Both
+/// are stored as confusable skeletons so look-alike spellings match too.
+pub struct UsernameLists {
+    reserved: HashSet<String>,
+    blocked: Vec<(String, String)>,
+}
+
+impl UsernameLists {
+    pub fn from_env() -> Self {
+        let reserved = read_list("RESERVED_USERNAMES_FILE", DEFAULT_RESERVED_USERNAMES);
+        let blocked = read_list("BLOCKED_USERNAME_TERMS_FILE", DEFAULT_BLOCKED_TERMS);
+
+        UsernameLists {
+            reserved: reserved
+                .iter()
+                .map(|name| username_skeleton(name))
+                .collect(),
+            blocked: blocked
+                .iter()
+                .map(|term| (fold_username(term), username_skeleton(term)))
+                .collect(),
+        }
+    }
+
+    pub fn is_reserved(&self, username: &str) -> bool {
+        self.reserved.contains(&username_skeleton(username))
+    }
+
+    pub fn contains_blocked_term(&self, username: &str) -> bool {
+        let folded = fold_username(username);
+        let skeleton = username_skeleton(username);
+
+        self.blocked
+            .iter()
+            .any(|(term, term_skeleton)| folded.contains(term) || skeleton.contains(term_skeleton))
+    }
+}
+
+pub static USERNAME_LISTS: LazyLock<UsernameLists> = LazyLock::new(UsernameLists::from_env);
+
+fn read_list(key: &str, default: &str) -> Vec<String> {
+    let path = env_or(key, String::new());
+
+    let contents = if path.is_empty() {
+        default.to_string()
+    } else {
+        fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!(
+                "WARNING: could not read {} ({}), using the defaults: {}",
+                key, path, e
+            );
+            default.to_string()
+        })
+    };
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// The form a username is stored and displayed in: trimmed and NFKC-normalised,
+/// so "ｊｏｈｎ" (fullwidth) becomes "john".
+pub fn normalise_username(raw: &str) -> String {
+    raw.trim().nfkc().collect()
+}
+
+/// NFKC_Casefold of a username, used for case-insensitive uniqueness and lookups.
+pub fn fold_username(username: &str) -> String {
+    let normalised: String = username.trim().nfkc().collect();
+
+    default_case_fold_str(&normalised).nfkc().collect()
+}
+
+/// Unicode confusable skeleton (UTS #39) of a username, so that look-alikes such
+/// as a Cyrillic "а" in place of a Latin "a" map to the same value.
+pub fn username_skeleton(username: &str) -> String {
+    skeleton(&fold_username(username)).collect()
+}
+
+/// Folds `username_normalised` and fills in `username_skeleton` for rows
+/// created before usernames were normalised, since neither can be computed in
+/// SQL. Those rows are the ones without a skeleton. A row whose folded name
+/// collides with another user's is left as it is and reported, and has to be
+/// renamed before it can be folded.
+pub async fn backfill_usernames(pool: &PGPool) -> Result<usize, AppError> {
+    use crate::schema::users::dsl::*;
+    use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+    let mut conn = pool.get().await?;
+
+    let pending: Vec<(Uuid, String)> = users
+        .filter(username_skeleton.is_null())
+        .select((id, username))
+        .load(&mut conn)
+        .await?;
+
+    let mut folded = 0;
+
+    for (user_id, name) in &pending {
+        let result = diesel::update(users.filter(id.eq(user_id)))
+            .set((
+                username_normalised.eq(fold_username(name)),
+                username_skeleton.eq(crate::username::username_skeleton(name)),
+            ))
+            .execute(&mut conn)
+            .await;
+
+        match result {
+            Ok(_) => folded += 1,
+            Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => eprintln!(
+                "{:?}: WARNING: username {:?} of user {} collides with another user after normalisation, rename it",
+                Utc::now().timestamp() as usize,
+                name,
+                user_id
+            ),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(folded)
+}
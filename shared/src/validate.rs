@@ -1,172 +1,461 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+
 use actix_web::web;
-use base64::prelude::*;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use diesel::dsl::{exists, not};
 use diesel::prelude::*;
-use diesel::result::{DatabaseErrorKind as DieselDbError, Error as DieselError};
-use diesel_async::RunQueryDsl;
-use image::load_from_memory;
+use diesel::result::{DatabaseErrorKind as DieselDbError, Error as DieselError, QueryResult};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use futures_util::future::OptionFuture;
+use futures_util::try_join;
 use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use unicode_security::MixedScript;
+use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
 
 use super::database::PGPool;
+use super::error::AppError;
+use super::password::{PASSWORD_POLICY, estimate_strength};
+use super::phone::normalise_phone_number;
+use super::username::{USERNAME_LISTS, USERNAME_POLICY, fold_username};
 
-const LOWERCASE_REGEX: &str = "[a-z]";
-const UPPERCASE_REGEX: &str = "[A-Z]";
-const NUMERIC_REGEX: &str = "[0-9]";
-const SPECIAL_REGEX: &str = "[^a-zA-Z0-9]";
 const EMAIL_REGEX: &str = r"^[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}$";
-const PHONE_NUMBER_REGEX: &str = r"^\+?[0-9]{7,15}$";
 
-pub fn validate_existing_username(username: &str) -> bool {
-    let valid_username = (username.len() >= 8 && username.len() <= 16)
-        && (username.chars().all(char::is_alphanumeric));
+const BIO_MIN_LEN: usize = 1;
+const BIO_MAX_LEN: usize = 500;
 
-    valid_username
+const DISPLAY_NAME_MAX_LEN: usize = 32;
+const PRONOUNS_MAX_LEN: usize = 24;
+const STATUS_TEXT_MAX_LEN: usize = 100;
+const NICKNAME_MAX_LEN: usize = 32;
+const FRIEND_LIST_NAME_MAX_LEN: usize = 32;
+
+/// A single failed rule for a field, e.g. `{"code":"length","params":{"min":8,"max":16}}`.
+#[derive(Debug, Serialize)]
+pub struct ValidationError {
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<&'static str, Value>,
 }
 
-pub async fn validate_new_username(
-    pool: web::Data<PGPool>,
-    new_username: &str,
-) -> Result<bool, DieselError> {
-    let valid_username = (new_username.len() >= 8 && new_username.len() <= 16)
-        && (new_username.chars().all(char::is_alphanumeric));
-
-    if valid_username {
-        use crate::models::User;
-        use crate::schema::users::dsl::*;
-
-        let mut conn = pool.get().await.map_err(|e| {
-            eprintln!(
-                "{:?}: Failed to acquire DB connection: {:?}",
-                Utc::now().timestamp() as usize,
-                e
-            );
-            DieselError::DatabaseError(DieselDbError::UnableToSendCommand, Box::new(e.to_string()))
-        })?;
-
-        let user_result = users
-            .filter(username.ilike(new_username))
-            .first::<User>(&mut conn)
-            .await;
-
-        if user_result.is_ok() {
-            Err(DieselError::DatabaseError(
-                DieselDbError::UniqueViolation,
-                Box::new("username taken".to_string()),
-            ))
+/// Every failed rule of a request, keyed by field name, so clients can
+/// highlight all invalid fields after a single round trip.
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct ValidationErrors(BTreeMap<&'static str, Vec<ValidationError>>);
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &'static str, code: &'static str) {
+        self.add_with_params(field, code, BTreeMap::new());
+    }
+
+    pub fn add_with_params(
+        &mut self,
+        field: &'static str,
+        code: &'static str,
+        params: BTreeMap<&'static str, Value>,
+    ) {
+        self.0
+            .entry(field)
+            .or_default()
+            .push(ValidationError { code, params });
+    }
+
+    pub fn add_length(&mut self, field: &'static str, min: usize, max: usize) {
+        self.add_with_params(
+            field,
+            "length",
+            BTreeMap::from([("min", min.into()), ("max", max.into())]),
+        );
+    }
+
+    pub fn has_field(&self, field: &str) -> bool {
+        self.0.contains_key(field)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn into_result(self) -> Result<(), AppError> {
+        if self.is_empty() {
+            Ok(())
         } else {
-            Ok(true)
+            Err(AppError::Validation(self))
         }
-    } else {
-        Ok(false)
     }
 }
 
-pub async fn validate_email(pool: web::Data<PGPool>, new_email: &str) -> Result<bool, DieselError> {
+/// Expects a username already passed through `normalise_username`.
+pub fn validate_existing_username(username: &str) -> bool {
+    let policy = &*USERNAME_POLICY;
+    let length = username.chars().count();
+
+    (length >= policy.min_length && length <= policy.max_length)
+        && policy.allowed_pattern.is_match(username)
+}
+
+/// Expects a username already passed through `normalise_username`.
+pub fn validate_username_format(username: &str, errors: &mut ValidationErrors) {
+    let policy = &*USERNAME_POLICY;
+    let length = username.chars().count();
+
+    if length < policy.min_length || length > policy.max_length {
+        errors.add_length("username", policy.min_length, policy.max_length);
+    }
+
+    if !policy.allowed_pattern.is_match(username) {
+        errors.add("username", "invalid_characters");
+    }
+
+    if !username.is_single_script() {
+        errors.add("username", "mixed_script");
+    }
+
+    if USERNAME_LISTS.contains_blocked_term(username) {
+        errors.add("username", "blocked");
+    }
+}
+
+pub fn validate_email_format(email: &str, errors: &mut ValidationErrors) {
     let email_re = Regex::new(EMAIL_REGEX).unwrap();
-    let valid_email = email_re.is_match(&new_email);
-
-    if valid_email {
-        use crate::models::User;
-        use crate::schema::users::dsl::*;
-
-        let mut conn = pool.get().await.map_err(|e| {
-            eprintln!(
-                "{:?}: Failed to acquire DB connection: {:?}",
-                Utc::now().timestamp() as usize,
-                e
-            );
-            DieselError::DatabaseError(DieselDbError::UnableToSendCommand, Box::new(e.to_string()))
-        })?;
-
-        let user_result = users
-            .filter(email.ilike(new_email))
-            .first::<User>(&mut conn)
-            .await;
-
-        if user_result.is_ok() {
-            Err(DieselError::DatabaseError(
-                DieselDbError::UniqueViolation,
-                Box::new("email taken".to_string()),
-            ))
-        } else {
-            Ok(true)
-        }
-    } else {
-        Ok(false)
+
+    if !email_re.is_match(email) {
+        errors.add("email", "invalid_format");
     }
 }
 
-pub async fn validate_phone_number(
-    pool: web::Data<PGPool>,
+/// Returns the E.164 form of `phone_number` when it is a valid mobile number.
+pub fn validate_phone_number_format(
+    phone_number: &str,
+    errors: &mut ValidationErrors,
+) -> Option<String> {
+    normalise_phone_number(phone_number)
+        .map_err(|e| errors.add("phone_number", e.code()))
+        .ok()
+}
+
+/// Resolves to `true` when no other user holds the case-folded form of
+/// `new_username`. The query is sent as soon as the future is polled, so several
+/// checks can be pipelined on one connection.
+pub fn validate_new_username(
+    conn: &mut AsyncPgConnection,
+    current_user: Uuid,
+    new_username: &str,
+) -> impl Future<Output = QueryResult<bool>> + use<> {
+    use crate::schema::users::dsl::*;
+
+    diesel::select(not(exists(
+        users
+            .filter(username_normalised.eq(fold_username(new_username)))
+            .filter(id.ne(current_user)),
+    )))
+    .get_result::<bool>(conn)
+}
+
+/// Resolves to `true` when no other user has a username that is visually
+/// confusable with `new_username`.
+pub fn validate_username_confusables(
+    conn: &mut AsyncPgConnection,
+    current_user: Uuid,
+    new_username: &str,
+) -> impl Future<Output = QueryResult<bool>> + use<> {
+    use crate::schema::users::dsl::*;
+
+    let skeleton = crate::username::username_skeleton(new_username);
+
+    diesel::select(not(exists(
+        users
+            .filter(username_skeleton.eq(skeleton))
+            .filter(id.ne(current_user)),
+    )))
+    .get_result::<bool>(conn)
+}
+
+/// Resolves to `true` unless another user gave up `new_username` within the hold
+/// period. Users may always take back their own former names.
+pub fn validate_username_not_held(
+    conn: &mut AsyncPgConnection,
+    current_user: Uuid,
+    new_username: &str,
+) -> impl Future<Output = QueryResult<bool>> + use<> {
+    use crate::schema::username_history::dsl::*;
+
+    let held_since = Utc::now() - USERNAME_POLICY.hold_period;
+
+    diesel::select(not(exists(
+        username_history
+            .filter(username_normalised.eq(fold_username(new_username)))
+            .filter(changed_at.gt(held_since))
+            .filter(user_id.ne(current_user)),
+    )))
+    .get_result::<bool>(conn)
+}
+
+/// Resolves to `true` when an admin has granted the reserved `new_username` to
+/// `current_user`.
+pub fn validate_reserved_username(
+    conn: &mut AsyncPgConnection,
+    current_user: Uuid,
+    new_username: &str,
+) -> impl Future<Output = QueryResult<bool>> + use<> {
+    use crate::schema::reserved_username_grant::dsl::*;
+
+    diesel::select(exists(
+        reserved_username_grant
+            .filter(username_normalised.eq(fold_username(new_username)))
+            .filter(user_id.eq(current_user)),
+    ))
+    .get_result::<bool>(conn)
+}
+
+/// Resolves to `true` when no other user has registered `new_email`.
+pub fn validate_email(
+    conn: &mut AsyncPgConnection,
+    current_user: Uuid,
+    new_email: &str,
+) -> impl Future<Output = QueryResult<bool>> + use<> {
+    use crate::schema::users::dsl::*;
+
+    diesel::select(not(exists(
+        users
+            .filter(email.ilike(new_email.to_string()))
+            .filter(id.ne(current_user)),
+    )))
+    .get_result::<bool>(conn)
+}
+
+/// Resolves to `true` when no other user has registered `new_phone_number`, which
+/// must already be in E.164 form.
+pub fn validate_phone_number(
+    conn: &mut AsyncPgConnection,
+    current_user: Uuid,
     new_phone_number: &str,
-) -> Result<bool, DieselError> {
-    let phone_re = Regex::new(PHONE_NUMBER_REGEX).unwrap();
-    let valid_phone_number = phone_re.is_match(new_phone_number);
-
-    if valid_phone_number {
-        use crate::models::User;
-        use crate::schema::users::dsl::*;
-
-        let mut conn = pool.get().await.map_err(|e| {
-            eprintln!(
-                "{:?}: Failed to acquire DB connection: {:?}",
-                Utc::now().timestamp() as usize,
-                e
-            );
-            DieselError::DatabaseError(DieselDbError::UnableToSendCommand, Box::new(e.to_string()))
-        })?;
-
-        let user_result = users
-            .filter(phone_number.eq(new_phone_number))
-            .first::<User>(&mut conn)
-            .await;
-
-        if user_result.is_ok() {
-            Err(DieselError::DatabaseError(
-                DieselDbError::UniqueViolation,
-                Box::new("phone number taken".to_string()),
-            ))
-        } else {
-            Ok(true)
-        }
-    } else {
-        Ok(false)
+) -> impl Future<Output = QueryResult<bool>> + use<> {
+    use crate::schema::users::dsl::*;
+
+    diesel::select(not(exists(
+        users
+            .filter(phone_number.eq(new_phone_number.to_string()))
+            .filter(id.ne(current_user)),
+    )))
+    .get_result::<bool>(conn)
+}
+
+/// Runs the uniqueness checks for every given field concurrently on a single
+/// connection, recording a `taken` error for each one already in use. Fields
+/// that already failed format validation are skipped, and `current_user` is
+/// excluded so users can re-submit or re-case their own values.
+pub async fn validate_unique_fields(
+    pool: web::Data<PGPool>,
+    current_user: Option<Uuid>,
+    username: Option<&str>,
+    email: Option<&str>,
+    phone_number: Option<&str>,
+    errors: &mut ValidationErrors,
+) -> Result<(), AppError> {
+    let username = username.filter(|_| !errors.has_field("username"));
+    let email = email.filter(|_| !errors.has_field("email"));
+    let phone_number = phone_number.filter(|_| !errors.has_field("phone_number"));
+
+    if username.is_none() && email.is_none() && phone_number.is_none() {
+        return Ok(());
+    }
+
+    let current_user = current_user.unwrap_or_default();
+
+    let mut conn = pool.get().await?;
+    let conn: &mut AsyncPgConnection = &mut conn;
+
+    let username_check: OptionFuture<_> = username
+        .map(|value| validate_new_username(conn, current_user, value))
+        .into();
+    let confusable_check: OptionFuture<_> = username
+        .map(|value| validate_username_confusables(conn, current_user, value))
+        .into();
+    let held_check: OptionFuture<_> = username
+        .map(|value| validate_username_not_held(conn, current_user, value))
+        .into();
+    let reserved_check: OptionFuture<_> = username
+        .filter(|value| USERNAME_LISTS.is_reserved(value))
+        .map(|value| validate_reserved_username(conn, current_user, value))
+        .into();
+    let email_check: OptionFuture<_> = email
+        .map(|value| validate_email(conn, current_user, value))
+        .into();
+    let phone_number_check: OptionFuture<_> = phone_number
+        .map(|value| validate_phone_number(conn, current_user, value))
+        .into();
+
+    let (username_free, not_held, no_confusables, reserved_granted, email_free, phone_number_free) =
+        try_join!(
+            async { username_check.await.transpose() },
+            async { held_check.await.transpose() },
+            async { confusable_check.await.transpose() },
+            async { reserved_check.await.transpose() },
+            async { email_check.await.transpose() },
+            async { phone_number_check.await.transpose() },
+        )?;
+
+    if reserved_granted == Some(false) {
+        errors.add("username", "reserved");
+    } else if username_free == Some(false) {
+        errors.add("username", "taken");
+    } else if not_held == Some(false) {
+        errors.add("username", "held");
+    } else if no_confusables == Some(false) {
+        errors.add("username", "confusable");
+    }
+
+    if email_free == Some(false) {
+        errors.add("email", "taken");
+    }
+
+    if phone_number_free == Some(false) {
+        errors.add("phone_number", "taken");
+    }
+
+    Ok(())
+}
+
+/// Used at login, where only the length is checked so that tightening the
+/// policy does not lock out existing accounts.
+pub fn validate_existing_password(password: &str) -> bool {
+    let length = password.chars().count();
+
+    length > 0 && length <= PASSWORD_POLICY.max_length
+}
+
+/// Checks `password` against the configured policy. `user_inputs` (username,
+/// email, ...) count as dictionary words when estimating its strength.
+pub fn validate_password(password: &str, user_inputs: &[&str], errors: &mut ValidationErrors) {
+    let policy = &*PASSWORD_POLICY;
+    let length = password.chars().count();
+
+    if length < policy.min_length || length > policy.max_length {
+        errors.add_length("password", policy.min_length, policy.max_length);
+    }
+
+    if !policy.allow_non_ascii && password.chars().any(|c| !(' '..='~').contains(&c)) {
+        errors.add("password", "invalid_characters");
+    }
+
+    if policy.require_lowercase && !password.chars().any(char::is_lowercase) {
+        errors.add("password", "missing_lowercase");
+    }
+
+    if policy.require_uppercase && !password.chars().any(char::is_uppercase) {
+        errors.add("password", "missing_uppercase");
+    }
+
+    if policy.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+        errors.add("password", "missing_digit");
+    }
+
+    if policy.require_special && password.chars().all(|c| c.is_ascii_alphanumeric()) {
+        errors.add("password", "missing_special");
+    }
+
+    let strength = estimate_strength(password, user_inputs);
+
+    if strength.score < policy.min_score {
+        errors.add_with_params(
+            "password",
+            "too_weak",
+            BTreeMap::from([
+                ("score", strength.score.into()),
+                ("min_score", policy.min_score.into()),
+            ]),
+        );
+    }
+
+    if policy.is_breached(password) {
+        errors.add("password", "breached");
+    }
+}
+
+pub fn validate_bio(bio: &str, errors: &mut ValidationErrors) {
+    if bio.len() < BIO_MIN_LEN || bio.len() > BIO_MAX_LEN {
+        errors.add_length("bio", BIO_MIN_LEN, BIO_MAX_LEN);
     }
 }
 
-pub fn validate_password(password: String) -> bool {
-    // TODO: block emoji from password
-    let lower_re = Regex::new(LOWERCASE_REGEX).unwrap();
-    let upper_re = Regex::new(UPPERCASE_REGEX).unwrap();
-    let num_re = Regex::new(NUMERIC_REGEX).unwrap();
-    let special_re = Regex::new(SPECIAL_REGEX).unwrap();
-    let valid_password = (password.len() >= 12 && password.len() <= 64)
-        && (lower_re.is_match(&password))
-        && (upper_re.is_match(&password))
-        && (num_re.is_match(&password))
-        && (special_re.is_match(&password));
+/// Lengths are counted in grapheme clusters, so an emoji built from several
+/// code points counts once.
+fn validate_free_text(field: &'static str, text: &str, max: usize, errors: &mut ValidationErrors) {
+    let length = text.graphemes(true).count();
 
-    valid_password
+    if length == 0 || length > max {
+        errors.add_length(field, 1, max);
+    }
+
+    if text.chars().any(char::is_control) {
+        errors.add(field, "invalid_characters");
+    }
+}
+
+pub fn validate_display_name(display_name: &str, errors: &mut ValidationErrors) {
+    validate_free_text("display_name", display_name, DISPLAY_NAME_MAX_LEN, errors);
 }
 
-pub fn validate_bio(bio: &str) -> bool {
-    let valid_bio = bio.len() >= 1 && bio.len() <= 500;
+pub fn validate_pronouns(pronouns: &str, errors: &mut ValidationErrors) {
+    validate_free_text("pronouns", pronouns, PRONOUNS_MAX_LEN, errors);
+}
 
-    valid_bio
+pub fn validate_status_text(status_text: &str, errors: &mut ValidationErrors) {
+    validate_free_text("status_text", status_text, STATUS_TEXT_MAX_LEN, errors);
 }
 
-pub fn validate_profile_pic(profile_pic: &str) -> bool {
-    // remove the "data:image/..." prefix
-    let base64_data = if let Some(idx) = profile_pic.find(",") {
-        &profile_pic[idx + 1..]
-    } else {
-        profile_pic
-    };
+pub fn validate_nickname(nickname: &str, errors: &mut ValidationErrors) {
+    validate_free_text("nickname", nickname, NICKNAME_MAX_LEN, errors);
+}
 
-    // decode and load as an image
-    BASE64_STANDARD
-        .decode(base64_data)
-        .ok()
-        .and_then(|bytes| load_from_memory(&bytes).ok())
-        .is_some()
+pub fn validate_friend_list_name(name: &str, errors: &mut ValidationErrors) {
+    validate_free_text("name", name, FRIEND_LIST_NAME_MAX_LEN, errors);
+}
+
+/// Expects a single emoji, including ZWJ sequences and skin tone variants.
+pub fn validate_status_emoji(status_emoji: &str, errors: &mut ValidationErrors) {
+    if emojis::get(status_emoji).is_none() {
+        errors.add("status_emoji", "invalid_emoji");
+    }
+}
+
+pub fn validate_status_expires_at(expires_at: DateTime<Utc>, errors: &mut ValidationErrors) {
+    if expires_at <= Utc::now() {
+        errors.add("status_expires_at", "in_past");
+    }
+}
+
+/// Expects an IANA time zone name such as `Europe/London`.
+pub fn validate_timezone(timezone: &str, errors: &mut ValidationErrors) {
+    if timezone.parse::<Tz>().is_err() {
+        errors.add("timezone", "invalid_timezone");
+    }
+}
+
+/// Maps a unique constraint violation on `users` to a `taken` validation error
+/// for the matching field. The pre-insert checks are racy, so the database
+/// constraints are the final word on uniqueness.
+pub fn unique_violation_to_validation(e: DieselError) -> AppError {
+    if let DieselError::DatabaseError(DieselDbError::UniqueViolation, info) = &e {
+        let field = match info.constraint_name() {
+            Some("users_username_key" | "idx_users_username_normalised") => Some("username"),
+            Some("users_email_key") => Some("email"),
+            Some("users_phone_number_key") => Some("phone_number"),
+            _ => None,
+        };
+
+        if let Some(field) = field {
+            let mut errors = ValidationErrors::default();
+            errors.add(field, "taken");
+            return AppError::Validation(errors);
+        }
+    }
+
+    e.into()
 }